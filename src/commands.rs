@@ -1,21 +1,121 @@
+use crate::error::MurenError;
 use crate::extensions::{find_extensions_from_content, has_correct_extension};
+use crate::target_fs::{NameProblem, TargetFs};
 use colored::Colorize;
-use regex::Regex;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
 use std::path::{Path, PathBuf};
 use unidecode::unidecode;
 
-#[derive(Clone)]
+/// Why a rename cannot be performed as suggested.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Conflict {
+    /// The new name is already taken by a file that is not renamed in the batch.
+    ExistingFile,
+    /// Another file of the batch gets the same new name.
+    Duplicate,
+    /// The new name lost bytes of the old one that are not valid UTF-8.
+    LossyName,
+    /// The new name is not valid on the target filesystem.
+    InvalidName {
+        target: TargetFs,
+        problem: NameProblem,
+    },
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Conflict::ExistingFile => write!(f, "target exists"),
+            Conflict::Duplicate => write!(f, "duplicate target"),
+            Conflict::LossyName => write!(f, "name not valid UTF-8"),
+            Conflict::InvalidName { target, problem } => write!(f, "invalid on {target}: {problem}"),
+        }
+    }
+}
+
+/// How a conflict was dealt with.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Resolution {
+    /// The file keeps its name instead of the proposed one.
+    Skipped {
+        #[serde(with = "path_bytes")]
+        proposed: PathBuf,
+    },
+    /// The existing file is replaced.
+    Overwrite,
+    /// The new name got a suffix to make it unique.
+    Suffixed,
+}
+
+impl Display for Resolution {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Resolution::Skipped { proposed } => {
+                write!(f, "skipped {}", proposed.to_string_lossy())
+            }
+            Resolution::Overwrite => write!(f, "overwrite"),
+            Resolution::Suffixed => write!(f, "suffix added"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RenameIntent {
+    #[serde(with = "path_bytes")]
     pub old_name: PathBuf,
+    #[serde(with = "path_bytes")]
     pub new_name: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<Conflict>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
 }
 
 impl RenameIntent {
+    pub fn new(old_name: PathBuf, new_name: PathBuf) -> Self {
+        RenameIntent {
+            old_name,
+            new_name,
+            conflict: None,
+            resolution: None,
+        }
+    }
+
     /// Is the new name different from the old one?
     pub fn is_changed(&self) -> bool {
         self.old_name != self.new_name
     }
+
+    /// Were bytes of the old name that are not valid UTF-8 replaced by `�` in the new name?
+    pub fn is_lossy(&self) -> bool {
+        let replacements = |path: &Path| -> usize {
+            name_bytes(path)
+                .utf8_chunks()
+                .map(|chunk| chunk.valid().matches(char::REPLACEMENT_CHARACTER).count())
+                .sum()
+        };
+        self.old_name.to_str().is_none() && replacements(&self.new_name) > replacements(&self.old_name)
+    }
+
+    /// Is there a conflict that prevents the rename?
+    pub fn is_blocked(&self) -> bool {
+        self.conflict.is_some() && self.resolution.is_none()
+    }
+
+    /// Write the conflict and its resolution (if any) after the names.
+    pub fn fmt_annotation(&self, f: &mut Formatter) -> std::fmt::Result {
+        match (&self.conflict, &self.resolution) {
+            (Some(conflict), Some(resolution)) => {
+                write!(f, " {}", format!("({conflict}, {resolution})").yellow())
+            }
+            (Some(conflict), None) => write!(f, " {}", format!("({conflict})").red()),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Display for RenameIntent {
@@ -26,34 +126,143 @@ impl Display for RenameIntent {
                 "{0} → {1}",
                 self.old_name.to_string_lossy().red(),
                 self.new_name.to_string_lossy().green()
-            )
+            )?;
         } else {
-            write!(f, "{0} =", self.old_name.to_string_lossy(),)
+            write!(f, "{0} =", self.old_name.to_string_lossy(),)?;
         }
+        self.fmt_annotation(f)
     }
 }
 
+/// The name as bytes; on unix including those that are not valid UTF-8.
+#[cfg(unix)]
+fn name_bytes(name: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(name.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &Path) -> Cow<'_, [u8]> {
+    match name.to_string_lossy() {
+        Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+        Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Replace all occurrences of the (non-empty) pattern, left to right.
+fn replace_bytes(name: &[u8], pattern: &[u8], replacement: &[u8]) -> Vec<u8> {
+    if pattern.is_empty() {
+        return name.to_vec();
+    }
+    let mut replaced = Vec::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+    {
+        replaced.extend_from_slice(&rest[..start]);
+        replaced.extend_from_slice(replacement);
+        rest = &rest[start + pattern.len()..];
+    }
+    replaced.extend_from_slice(rest);
+    replaced
+}
+
+/// Store paths as strings, or as arrays of bytes when they are not valid UTF-8.
+pub mod path_bytes {
+    use super::{name_bytes, path_from_bytes};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => serializer.collect_seq(name_bytes(path).iter()),
+        }
+    }
+
+    pub fn serialize_option<S: Serializer>(
+        path: &Option<&Path>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        struct Stored<'a>(&'a Path);
+
+        impl serde::Serialize for Stored<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serialize(self.0, serializer)
+            }
+        }
+
+        match path {
+            Some(path) => serializer.serialize_some(&Stored(path)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Text(String),
+            Bytes(Vec<u8>),
+        }
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Text(text) => PathBuf::from(text),
+            Stored::Bytes(bytes) => path_from_bytes(bytes),
+        })
+    }
+}
+
+/// Change the text of the name, keeping the bytes that are not valid UTF-8 as they are.
+fn map_text(name: &Path, change: impl Fn(&str) -> String) -> PathBuf {
+    let bytes = name_bytes(name);
+    let mut new_name = Vec::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        new_name.extend_from_slice(change(chunk.valid()).as_bytes());
+        new_name.extend_from_slice(chunk.invalid());
+    }
+    path_from_bytes(new_name)
+}
+
 pub trait RenameCommand {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf;
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError>;
 
-    fn suggest_renames(&self, files: &[PathBuf]) -> Vec<RenameIntent> {
+    fn suggest_renames(&self, files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
         files
             .iter()
-            .map(|path| RenameIntent {
-                old_name: path.clone(),
-                new_name: self.suggest_new_name(path),
-            })
+            .map(|path| Ok(RenameIntent::new(path.clone(), self.suggest_new_name(path)?)))
             .collect()
     }
+
+    /// Does the command need the whole path (e.g. to read the file) even if it renames only a part?
+    fn needs_full_path(&self) -> bool {
+        false
+    }
 }
 
-pub struct Normalize;
+pub struct Normalize {
+    /// Also make the names valid on this filesystem.
+    pub target: Option<TargetFs>,
+}
 
 impl RenameCommand for Normalize {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let path_str = old_name.to_string_lossy().to_string();
-        let new_name = unidecode(&path_str).replace(' ', "_"); //#.to_lowercase();
-        PathBuf::from(new_name)
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let new_name = map_text(old_name, |text| unidecode(text).replace(' ', "_")); //#.to_lowercase();
+        Ok(match self.target {
+            Some(target) => target.fix_path(&new_name),
+            None => new_name,
+        })
     }
 }
 
@@ -62,10 +271,10 @@ pub struct SetExtension {
 }
 
 impl RenameCommand for SetExtension {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
         let mut new_name = old_name.to_path_buf();
         new_name.set_extension(&self.extension);
-        new_name
+        Ok(new_name)
     }
 }
 
@@ -74,9 +283,9 @@ pub struct Remove {
 }
 
 impl RenameCommand for Remove {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let new_name = old_name.to_string_lossy().replace(&self.pattern, "");
-        PathBuf::from(new_name)
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let new_name = replace_bytes(&name_bytes(old_name), self.pattern.as_bytes(), b"");
+        Ok(path_from_bytes(new_name))
     }
 }
 
@@ -87,15 +296,15 @@ pub struct Replace {
 }
 
 impl RenameCommand for Replace {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let path_str = old_name.to_string_lossy().to_string();
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let name = name_bytes(old_name);
+        let replacement = self.replacement.as_bytes();
         let new_name = if self.is_regex {
-            let re = Regex::new(&self.pattern).unwrap();
-            re.replace_all(&path_str, &self.replacement).to_string()
+            Regex::new(&self.pattern)?.replace_all(&name, replacement).into_owned()
         } else {
-            path_str.replace(&self.pattern, &self.replacement)
+            replace_bytes(&name, self.pattern.as_bytes(), replacement)
         };
-        PathBuf::from(new_name)
+        Ok(path_from_bytes(new_name))
     }
 }
 
@@ -104,13 +313,11 @@ pub struct ChangeCase {
 }
 
 impl RenameCommand for ChangeCase {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let path_str = old_name.to_string_lossy().to_string();
-        let new_name = match self.upper {
-            true => path_str.to_uppercase(),
-            false => path_str.to_lowercase(),
-        };
-        PathBuf::from(new_name)
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        Ok(map_text(old_name, |text| match self.upper {
+            true => text.to_uppercase(),
+            false => text.to_lowercase(),
+        }))
     }
 }
 
@@ -119,20 +326,26 @@ pub struct FixExtension {
 }
 
 impl RenameCommand for FixExtension {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let possible_extensions = find_extensions_from_content(old_name);
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let possible_extensions = find_extensions_from_content(old_name)?;
         let mut new_name = old_name.to_path_buf();
         if !has_correct_extension(old_name, &possible_extensions) {
-            let mut new_extension = possible_extensions[0].clone();
+            let mut new_extension = OsString::from(&possible_extensions[0]);
             if self.append {
                 if let Some(old_extension) = new_name.extension() {
-                    new_extension.insert(0, '.');
-                    new_extension.insert_str(0, old_extension.to_str().unwrap())
+                    let mut appended = old_extension.to_os_string();
+                    appended.push(".");
+                    appended.push(new_extension);
+                    new_extension = appended;
                 }
             }
             new_name.set_extension(new_extension);
         };
-        new_name
+        Ok(new_name)
+    }
+
+    fn needs_full_path(&self) -> bool {
+        true
     }
 }
 
@@ -141,10 +354,10 @@ pub struct Prefix {
 }
 
 impl RenameCommand for Prefix {
-    fn suggest_new_name(&self, old_name: &Path) -> PathBuf {
-        let mut new_name = self.prefix.clone();
-        new_name.push_str(old_name.to_string_lossy().to_string().as_str());
-        PathBuf::from(new_name)
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let mut new_name = OsString::from(&self.prefix);
+        new_name.push(old_name);
+        Ok(PathBuf::from(new_name))
     }
 }
 
@@ -160,7 +373,7 @@ mod tests {
         expected_names: &[&str],
     ) {
         let old: Vec<PathBuf> = old_names.iter().map(|&x| PathBuf::from(x)).collect();
-        let new_intents = command.suggest_renames(&old);
+        let new_intents = command.suggest_renames(&old).unwrap();
         let new: Vec<PathBuf> = new_intents
             .iter()
             .map(|intent| intent.new_name.clone())
@@ -195,6 +408,19 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_invalid_regex() {
+            let command = Replace {
+                pattern: String::from("(unclosed"),
+                replacement: String::from("a"),
+                is_regex: true,
+            };
+            assert!(matches!(
+                command.suggest_renames(&[PathBuf::from("a")]),
+                Err(MurenError::InvalidRegex(_))
+            ));
+        }
+
         #[test]
         fn test_non_regex() {
             let command = Replace {
@@ -235,12 +461,21 @@ mod tests {
     #[test]
     fn test_normalize() {
         assert_renames_correctly(
-            &Normalize,
+            &Normalize{ target: None },
             &["Abc", "hnědý", "Αθήνα & Σπάρτη", "mountain🗻"],
             &["Abc", "hnedy", "Athena_&_Sparte", "mountain"]
         );
     }
 
+    #[test]
+    fn test_normalize_for_windows() {
+        assert_renames_correctly(
+            &Normalize{ target: Some(TargetFs::Windows) },
+            &["Zápis: 1. část?", "con.txt", "done."],
+            &["Zapis__1._cast_", "con_.txt", "done"]
+        );
+    }
+
     mod test_set_extension {
         use super::*;
 
@@ -271,4 +506,38 @@ mod tests {
             &[".gitignore", "be", "AB"]
         )
     }
+
+    #[test]
+    fn test_replace_bytes() {
+        assert_eq!(replace_bytes(b"aaaa.txt", b"aa", b"b"), b"bb.txt");
+        assert_eq!(replace_bytes(b"a.txt", b".", b"$1"), b"a$1txt");
+        assert_eq!(replace_bytes(b"a.txt", b"", b"x"), b"a.txt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let latin1 = |bytes: &[u8]| PathBuf::from(std::ffi::OsStr::from_bytes(bytes));
+        let old = [latin1(b"caf\xe9 Ol\xe9.txt")];
+        let new_name = |command: &dyn RenameCommand| command.suggest_renames(&old).unwrap()[0].new_name.clone();
+
+        assert_eq!(new_name(&ChangeCase{ upper: true }), latin1(b"CAF\xe9 OL\xe9.TXT"));
+        assert_eq!(new_name(&Normalize{ target: None }), latin1(b"caf\xe9_Ol\xe9.txt"));
+        assert_eq!(new_name(&Prefix{ prefix: String::from("x_") }), latin1(b"x_caf\xe9 Ol\xe9.txt"));
+        assert_eq!(new_name(&Remove{ pattern: String::from("Ol") }), latin1(b"caf\xe9 \xe9.txt"));
+        assert_eq!(
+            new_name(&Replace{ pattern: String::from(r"\.(t)xt$"), replacement: String::from(".${1}ext"), is_regex: true }),
+            latin1(b"caf\xe9 Ol\xe9.text")
+        );
+
+        let lossy = RenameIntent::new(old[0].clone(), PathBuf::from(old[0].to_string_lossy().to_uppercase()));
+        assert!(lossy.is_lossy());
+        let intents = ChangeCase{ upper: true }.suggest_renames(&old).unwrap();
+        assert!(!intents[0].is_lossy());
+
+        let json = serde_json::to_string(&intents[0]).unwrap();
+        let stored: RenameIntent = serde_json::from_str(&json).unwrap();
+        assert_eq!((stored.old_name, stored.new_name), (intents[0].old_name.clone(), intents[0].new_name.clone()));
+    }
 }
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// How the filesystem tells apart two names.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NameComparison {
+    /// Names are equal only if they are identical (most Linux filesystems).
+    #[default]
+    Exact,
+    /// NFC and NFD spellings of the same text are the same name.
+    Normalized,
+    /// Names differing only in case (or normalization) are the same name (exFAT, NTFS, APFS).
+    CaseInsensitive,
+}
+
+impl NameComparison {
+    /// A key under which all the names considered equal collide.
+    pub fn key(&self, path: &Path) -> PathBuf {
+        let text = match (self, path.to_str()) {
+            (NameComparison::Exact, _) | (_, None) => return path.to_path_buf(),
+            (_, Some(text)) => text,
+        };
+        match self {
+            NameComparison::CaseInsensitive => text.to_lowercase().nfc().collect::<String>(),
+            _ => text.nfc().collect::<String>(),
+        }
+        .into()
+    }
+
+    /// Guess the behaviour of the filesystem(s) on which the paths are (to be) created.
+    ///
+    /// Nothing is written to the disk: for a few files in the directory of each path
+    /// (or its nearest existing ancestor, if the directory is yet to be created), we check
+    /// whether a differently cased or normalized spelling of the name points to the same file.
+    pub fn detect(paths: &[PathBuf]) -> NameComparison {
+        let mut detected = NameComparison::Exact;
+        let mut probed_dirs = HashSet::new();
+
+        for path in paths {
+            let Some(dir) = probed_dir(path) else {
+                continue;
+            };
+            if !probed_dirs.insert(dir.clone()) {
+                continue;
+            }
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let names: Vec<PathBuf> = entries
+                .filter_map(Result::ok)
+                .take(PROBED_ENTRIES)
+                .map(|entry| entry.path())
+                .collect();
+            let respelled = |respell: fn(&str) -> String| {
+                names.iter().find_map(|path| {
+                    let name = path.file_name()?.to_str()?;
+                    let other = respell(name);
+                    (other != name).then(|| (path, path.with_file_name(other)))
+                })
+            };
+
+            if let Some((path, swapped)) = respelled(swap_case) {
+                if is_same_file(path, &swapped) {
+                    return NameComparison::CaseInsensitive;
+                }
+            }
+            if let Some((path, other)) = respelled(other_normalization) {
+                if is_same_file(path, &other) {
+                    detected = NameComparison::Normalized;
+                }
+            }
+        }
+        detected
+    }
+}
+
+/// How many files of a directory are searched for names to respell.
+const PROBED_ENTRIES: usize = 1000;
+
+/// The directory in which the path is created, or its nearest existing ancestor.
+fn probed_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        })
+        .find(|dir| dir.is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// The NFD spelling of an NFC name and vice versa.
+fn other_normalization(name: &str) -> String {
+    let nfc: String = name.nfc().collect();
+    match nfc == name {
+        true => name.nfd().collect(),
+        false => nfc,
+    }
+}
+
+fn swap_case(name: &str) -> String {
+    name.chars()
+        .flat_map(|c| -> Box<dyn Iterator<Item = char>> {
+            if c.is_lowercase() {
+                Box::new(c.to_uppercase())
+            } else {
+                Box::new(c.to_lowercase())
+            }
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (a.symlink_metadata(), b.symlink_metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    a.symlink_metadata().is_ok() && b.symlink_metadata().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let nfc = Path::new("hn\u{11b}d\u{fd}.txt");
+        let nfd = Path::new("hne\u{30c}dy\u{301}.txt");
+        let upper = Path::new("HN\u{11a}D\u{dd}.TXT");
+
+        assert_ne!(
+            NameComparison::Exact.key(nfc),
+            NameComparison::Exact.key(nfd)
+        );
+        assert_eq!(
+            NameComparison::Normalized.key(nfc),
+            NameComparison::Normalized.key(nfd)
+        );
+        assert_ne!(
+            NameComparison::Normalized.key(nfc),
+            NameComparison::Normalized.key(upper)
+        );
+        assert_eq!(
+            NameComparison::CaseInsensitive.key(nfd),
+            NameComparison::CaseInsensitive.key(upper)
+        );
+    }
+
+    #[test]
+    fn test_swap_case() {
+        assert_eq!(swap_case("Foo.txt"), "fOO.TXT");
+        assert_eq!(swap_case("123"), "123");
+    }
+
+    #[test]
+    fn test_detect_without_files() {
+        assert_eq!(
+            NameComparison::detect(&[PathBuf::from("/nonexistent/Foo.txt")]),
+            NameComparison::Exact
+        );
+    }
+
+    #[test]
+    fn test_probed_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            probed_dir(&dir.path().join("a.txt")),
+            Some(dir.path().to_path_buf())
+        );
+        assert_eq!(
+            probed_dir(&dir.path().join("missing/sub/a.txt")),
+            Some(dir.path().to_path_buf())
+        );
+        assert_eq!(probed_dir(Path::new("a.txt")), Some(PathBuf::from(".")));
+    }
+}
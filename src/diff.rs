@@ -0,0 +1,273 @@
+use std::fmt::{Display, Formatter};
+
+use colored::{ColoredString, Colorize};
+
+use crate::commands::RenameIntent;
+
+/// A part of the old or new name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    /// In both names.
+    Equal(String),
+    /// Only in the old name.
+    Delete(String),
+    /// Only in the new name.
+    Insert(String),
+}
+
+/// The units in which the names are compared.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Granularity {
+    Chars,
+    /// Runs of letters and digits, any other character is a word of its own.
+    Words,
+}
+
+/// Above this number of compared token pairs, the changed middle of the names is not diffed further.
+const MAX_TABLE_SIZE: usize = 1_000_000;
+
+/// How to turn the old name into the new one, as a sequence of segments.
+pub fn diff(old: &str, new: &str, granularity: Granularity) -> Vec<Segment> {
+    let old_tokens = tokenize(old, granularity);
+    let new_tokens = tokenize(new, granularity);
+
+    let prefix = old_tokens
+        .iter()
+        .zip(&new_tokens)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old_tokens[prefix..]
+        .iter()
+        .rev()
+        .zip(new_tokens[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old_tokens[prefix..old_tokens.len() - suffix];
+    let new_middle = &new_tokens[prefix..new_tokens.len() - suffix];
+
+    let mut segments = Vec::new();
+    push(&mut segments, Segment::Equal(old_tokens[..prefix].concat()));
+    if old_middle.len() * new_middle.len() <= MAX_TABLE_SIZE {
+        for segment in diff_tokens(old_middle, new_middle) {
+            push(&mut segments, segment);
+        }
+    } else {
+        push(&mut segments, Segment::Delete(old_middle.concat()));
+        push(&mut segments, Segment::Insert(new_middle.concat()));
+    }
+    push(
+        &mut segments,
+        Segment::Equal(old_tokens[old_tokens.len() - suffix..].concat()),
+    );
+    segments
+}
+
+fn tokenize(text: &str, granularity: Granularity) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let ends = match (granularity, chars.peek()) {
+            (_, None) => true,
+            (Granularity::Chars, _) => true,
+            (Granularity::Words, Some((_, next))) => {
+                !(c.is_alphanumeric() && next.is_alphanumeric())
+            }
+        };
+        if ends {
+            let end = index + c.len_utf8();
+            tokens.push(&text[start..end]);
+            start = end;
+        }
+    }
+    tokens
+}
+
+/// Longest common subsequence of the tokens.
+fn diff_tokens(old: &[&str], new: &[&str]) -> Vec<Segment> {
+    // lengths[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut segments = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            push(&mut segments, Segment::Equal(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            push(&mut segments, Segment::Delete(old[i].to_string()));
+            i += 1;
+        } else {
+            push(&mut segments, Segment::Insert(new[j].to_string()));
+            j += 1;
+        }
+    }
+    segments
+}
+
+/// Add the segment, merging it with the previous one of the same kind.
+fn push(segments: &mut Vec<Segment>, segment: Segment) {
+    let text = match &segment {
+        Segment::Equal(text) | Segment::Delete(text) | Segment::Insert(text) => text,
+    };
+    if text.is_empty() {
+        return;
+    }
+    match (segments.last_mut(), &segment) {
+        (Some(Segment::Equal(last)), Segment::Equal(text))
+        | (Some(Segment::Delete(last)), Segment::Delete(text))
+        | (Some(Segment::Insert(last)), Segment::Insert(text)) => last.push_str(text),
+        _ => segments.push(segment),
+    }
+}
+
+/// A rename shown with the changed parts of the names highlighted.
+pub struct HighlightedIntent<'a> {
+    pub intent: &'a RenameIntent,
+    /// Compare the names in these units (or just color them as a whole).
+    pub granularity: Option<Granularity>,
+    /// Pad the old names to this number of characters, to align the new names in a column.
+    pub old_width: usize,
+}
+
+impl Display for HighlightedIntent<'_> {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let intent = self.intent;
+        let old_name = intent.old_name.to_string_lossy();
+        let new_name = intent.new_name.to_string_lossy();
+        let padding = " ".repeat(self.old_width.saturating_sub(old_name.chars().count()));
+
+        if !intent.is_changed() {
+            write!(f, "{old_name}{padding} =")?;
+        } else if let Some(granularity) = self.granularity {
+            let segments = diff(&old_name, &new_name, granularity);
+            for segment in &segments {
+                match segment {
+                    Segment::Equal(text) => write!(f, "{}", text.red())?,
+                    Segment::Delete(text) => write!(f, "{}", changed(text.red()))?,
+                    Segment::Insert(_) => {}
+                }
+            }
+            write!(f, "{padding} → ")?;
+            for segment in &segments {
+                match segment {
+                    Segment::Equal(text) => write!(f, "{}", text.green())?,
+                    Segment::Insert(text) => write!(f, "{}", changed(text.green()))?,
+                    Segment::Delete(_) => {}
+                }
+            }
+        } else {
+            write!(f, "{}{padding} → {}", old_name.red(), new_name.green())?;
+        }
+        intent.fmt_annotation(f)
+    }
+}
+
+fn changed(text: ColoredString) -> ColoredString {
+    text.bold().reversed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn equal(text: &str) -> Segment {
+        Segment::Equal(text.to_string())
+    }
+
+    fn delete(text: &str) -> Segment {
+        Segment::Delete(text.to_string())
+    }
+
+    fn insert(text: &str) -> Segment {
+        Segment::Insert(text.to_string())
+    }
+
+    #[test]
+    fn test_char_diff() {
+        assert_eq!(
+            diff("IMG_0042.JPG", "img_0042.jpg", Granularity::Chars),
+            [
+                delete("IMG"),
+                insert("img"),
+                equal("_0042."),
+                delete("JPG"),
+                insert("jpg")
+            ]
+        );
+        assert_eq!(
+            diff("hnědý pes.txt", "hnedy_pes.txt", Granularity::Chars),
+            [
+                equal("hn"),
+                delete("ě"),
+                insert("e"),
+                equal("d"),
+                delete("ý "),
+                insert("y_"),
+                equal("pes.txt")
+            ]
+        );
+        assert_eq!(diff("same", "same", Granularity::Chars), [equal("same")]);
+        assert_eq!(diff("", "new", Granularity::Chars), [insert("new")]);
+    }
+
+    #[test]
+    fn test_word_diff() {
+        assert_eq!(
+            diff(
+                "holiday 2023 photo.jpg",
+                "holiday_2024_photo.jpg",
+                Granularity::Words
+            ),
+            [
+                equal("holiday"),
+                delete(" 2023 "),
+                insert("_2024_"),
+                equal("photo.jpg")
+            ]
+        );
+    }
+
+    /// The text without the ANSI color codes (which depend on the terminal).
+    fn plain(text: String) -> String {
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => chars.by_ref().take_while(|&c| c != 'm').for_each(drop),
+                c => plain.push(c),
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn test_highlighted_columns() {
+        let intent = RenameIntent::new(PathBuf::from("ab"), PathBuf::from("ac"));
+        let shown = HighlightedIntent {
+            intent: &intent,
+            granularity: Some(Granularity::Chars),
+            old_width: 4,
+        };
+        assert_eq!(plain(shown.to_string()), "ab   → ac");
+
+        let unchanged = RenameIntent::new(PathBuf::from("abc"), PathBuf::from("abc"));
+        let shown = HighlightedIntent {
+            intent: &unchanged,
+            granularity: None,
+            old_width: 4,
+        };
+        assert_eq!(plain(shown.to_string()), "abc  =");
+    }
+}
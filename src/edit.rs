@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::commands::{RenameCommand, RenameIntent};
+use crate::error::MurenError;
+
+/// Let the user type the new names in a text editor (like `vidir`).
+///
+/// Each file is written as a numbered line `N<tab>name`; lines can be reordered,
+/// but none of them can be removed.
+pub struct Edit {
+    /// The editor command, possibly with arguments (e.g. `code --wait`).
+    pub editor: String,
+}
+
+impl Edit {
+    /// The editor from `$VISUAL` or `$EDITOR`, `vi` if neither is set.
+    pub fn from_env() -> Self {
+        let editor = ["VISUAL", "EDITOR"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| String::from("vi"));
+        Edit { editor }
+    }
+
+    fn run_editor(&self, path: &Path) -> Result<(), MurenError> {
+        let mut words = self.editor.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| MurenError::EditorFailed(String::new()))?;
+        let mut command = process::Command::new(program);
+        command.args(words).arg(path);
+        // Stdin may hold the list of paths, the editor needs the terminal
+        #[cfg(unix)]
+        if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+            if let Ok(tty) = fs::File::open("/dev/tty") {
+                command.stdin(tty);
+            }
+        }
+        let status = command.status().map_err(|e| match e.kind() {
+            ErrorKind::NotFound => MurenError::EditorFailed(format!("{program} not found")),
+            _ => MurenError::EditorFailed(e.to_string()),
+        })?;
+        if !status.success() {
+            return Err(MurenError::EditorFailed(format!(
+                "{program} exited with {status}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl RenameCommand for Edit {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        Ok(self
+            .suggest_renames(&[old_name.to_path_buf()])?
+            .remove(0)
+            .new_name)
+    }
+
+    fn suggest_renames(&self, files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
+        let content = write_buffer(files)?;
+
+        let (path, mut buffer) = create_buffer_file()?;
+        let edited = buffer
+            .write_all(content.as_bytes())
+            .map_err(MurenError::from)
+            .and_then(|_| self.run_editor(&path))
+            .and_then(|_| Ok(fs::read_to_string(&path)?));
+        let _ = fs::remove_file(&path);
+
+        let new_names = read_buffer(&edited?, files)?;
+        Ok(files
+            .iter()
+            .cloned()
+            .zip(new_names)
+            .map(|(old_name, new_name)| RenameIntent::new(old_name, new_name))
+            .collect())
+    }
+
+    /// Bare basenames of files from different directories would be indistinguishable in the buffer.
+    fn needs_full_path(&self) -> bool {
+        true
+    }
+}
+
+/// A new file in the temporary directory.
+fn create_buffer_file() -> Result<(PathBuf, fs::File), MurenError> {
+    let mut n = 0;
+    loop {
+        let path = env::temp_dir().join(format!("muren-edit-{}-{n}.txt", process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// The numbered lines presented to the user (names that are not valid UTF-8 shown with `�`).
+fn write_buffer(files: &[PathBuf]) -> Result<String, MurenError> {
+    let mut content = String::new();
+    for (index, file) in files.iter().enumerate() {
+        let name = file.to_string_lossy();
+        if name.contains(['\n', '\r']) {
+            return Err(MurenError::InvalidEdit(format!(
+                "{name:?} contains a line break"
+            )));
+        }
+        content.push_str(&format!("{}\t{}\n", index + 1, name));
+    }
+    Ok(content)
+}
+
+/// The new names in the order of the files, checking that each file is there exactly once.
+fn read_buffer(content: &str, files: &[PathBuf]) -> Result<Vec<PathBuf>, MurenError> {
+    let mut new_names: HashMap<usize, PathBuf> = HashMap::new();
+    for (line_index, line) in content.lines().enumerate() {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid =
+            |message: String| MurenError::InvalidEdit(format!("line {line_number}: {message}"));
+
+        let (number, name) = line
+            .split_once('\t')
+            .ok_or_else(|| invalid(String::from("expected a number, a tab and the new name")))?;
+        let number: usize = match number.trim().parse() {
+            Ok(number) if (1..=files.len()).contains(&number) => number,
+            _ => return Err(invalid(format!("unknown file number {:?}", number.trim()))),
+        };
+        if name.is_empty() {
+            return Err(invalid(format!(
+                "empty name for {}",
+                files[number - 1].to_string_lossy()
+            )));
+        }
+        // An unchanged line keeps the exact old name, even if it is not valid UTF-8
+        let old_name = &files[number - 1];
+        let new_name = match old_name.to_string_lossy() == name {
+            true => old_name.clone(),
+            false => PathBuf::from(name),
+        };
+        if new_names.insert(number, new_name).is_some() {
+            return Err(invalid(format!(
+                "file number {number} appears more than once"
+            )));
+        }
+    }
+
+    files
+        .iter()
+        .enumerate()
+        .map(|(index, file)| {
+            new_names.remove(&(index + 1)).ok_or_else(|| {
+                MurenError::InvalidEdit(format!(
+                    "line of file {} ({}) is missing, leave it unchanged to keep the name",
+                    index + 1,
+                    file.to_string_lossy()
+                ))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<PathBuf> {
+        vec![PathBuf::from("a.txt"), PathBuf::from("dir/b c.txt")]
+    }
+
+    fn assert_invalid(content: &str, message: &str) {
+        match read_buffer(content, &files()) {
+            Err(MurenError::InvalidEdit(e)) => assert!(e.contains(message), "{e}"),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_write_buffer() {
+        assert_eq!(
+            write_buffer(&files()).unwrap(),
+            "1\ta.txt\n2\tdir/b c.txt\n"
+        );
+        assert!(write_buffer(&[PathBuf::from("a\nb")]).is_err());
+    }
+
+    #[test]
+    fn test_read_buffer() {
+        let new_names = read_buffer("2\tdir/d.txt\n\n1\te.txt\n", &files()).unwrap();
+        assert_eq!(
+            new_names,
+            [PathBuf::from("e.txt"), PathBuf::from("dir/d.txt")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_buffer() {
+        use std::os::unix::ffi::OsStrExt;
+        let files = [PathBuf::from(std::ffi::OsStr::from_bytes(b"caf\xe9"))];
+        let content = write_buffer(&files).unwrap();
+        assert_eq!(content, "1\tcaf\u{fffd}\n");
+        assert_eq!(read_buffer(&content, &files).unwrap(), files);
+        let edited = read_buffer("1\tCAF\u{fffd}\n", &files).unwrap();
+        assert!(RenameIntent::new(files[0].clone(), edited[0].clone()).is_lossy());
+    }
+
+    #[test]
+    fn test_read_invalid_buffer() {
+        assert_invalid("1\ta.txt\n", "file 2 (dir/b c.txt) is missing");
+        assert_invalid(
+            "1\ta.txt\n1\tb.txt\n2\tc.txt\n",
+            "line 2: file number 1 appears more",
+        );
+        assert_invalid("1\ta.txt\n3\tc.txt\n", "line 2: unknown file number \"3\"");
+        assert_invalid("1 a.txt\n", "line 1: expected a number");
+        assert_invalid("1\t\n2\tb", "line 1: empty name for a.txt");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_with_command() {
+        let edit = Edit {
+            editor: String::from("sed -i s/a.txt/f.txt/"),
+        };
+        let intents = edit.suggest_renames(&files()).unwrap();
+        assert_eq!(intents[0].new_name, PathBuf::from("f.txt"));
+        assert!(!intents[1].is_changed());
+
+        let failing = Edit {
+            editor: String::from("false"),
+        };
+        assert!(matches!(
+            failing.suggest_renames(&files()),
+            Err(MurenError::EditorFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+use crate::plan::PlanError;
+
+/// Everything that can go wrong in muren.
+#[derive(Debug)]
+pub enum MurenError {
+    /// The pattern given to `replace` is not a valid regular expression.
+    InvalidRegex(regex::Error),
+    /// The `file` utility needed to detect file types is not installed.
+    FileCommandMissing,
+    /// No subcommand was given on the command line.
+    NoCommand,
+    /// The command-line subcommand is not known.
+    UnknownCommand(String),
+    /// Some of the renames conflict with each other or with existing files.
+    Conflicts(usize),
+    /// The renames cannot be ordered safely.
+    Plan(PlanError),
+    /// The journal does not contain the requested batch.
+    UnknownBatch(u64),
+    /// The requested batch has already been undone.
+    AlreadyUndone(u64),
+    /// There is no batch in the journal that could be undone.
+    NothingToUndo,
+    /// The journal location could not be determined.
+    JournalUnavailable,
+    /// The saved plan cannot be read.
+    InvalidPlan(serde_json::Error),
+    /// These files were changed (or removed) after the plan had been saved.
+    SourcesChanged(Vec<PathBuf>),
+    /// These files of a plan do not exist.
+    MissingSources(Vec<PathBuf>),
+    /// The plan does not record the state of these files (e.g. written by hand).
+    UnverifiedSources(Vec<PathBuf>),
+    /// A path argument is not a valid glob pattern.
+    InvalidGlob(glob::PatternError),
+    /// A glob pattern does not match any file.
+    NoMatches(String),
+    /// The editor could not be started or did not finish successfully.
+    EditorFailed(String),
+    /// The names returned from the editor do not match the edited files.
+    InvalidEdit(String),
+    Io(io::Error),
+}
+
+impl Display for MurenError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            MurenError::InvalidRegex(e) => write!(f, "Invalid regular expression: {e}"),
+            MurenError::FileCommandMissing => write!(f, "`file` probably not installed"),
+            MurenError::NoCommand => write!(f, "No command provided"),
+            MurenError::UnknownCommand(name) => write!(f, "Unknown command: {name}"),
+            MurenError::Conflicts(count) => {
+                write!(f, "{count} files cannot be renamed because of conflicts")
+            }
+            MurenError::Plan(e) => write!(f, "{e}"),
+            MurenError::UnknownBatch(id) => write!(f, "There is no batch #{id} in the journal."),
+            MurenError::AlreadyUndone(id) => write!(f, "Batch #{id} is already undone."),
+            MurenError::NothingToUndo => write!(f, "There is nothing to undo."),
+            MurenError::JournalUnavailable => write!(f, "The journal is not available."),
+            MurenError::InvalidPlan(e) => write!(f, "Invalid plan: {e}"),
+            MurenError::SourcesChanged(paths) => {
+                let names: Vec<_> = paths.iter().map(|p| p.to_string_lossy()).collect();
+                write!(
+                    f,
+                    "{} files changed since the plan was made: {}",
+                    paths.len(),
+                    names.join(", ")
+                )
+            }
+            MurenError::MissingSources(paths) => {
+                let names: Vec<_> = paths.iter().map(|p| p.to_string_lossy()).collect();
+                write!(
+                    f,
+                    "{} files do not exist: {}",
+                    paths.len(),
+                    names.join(", ")
+                )
+            }
+            MurenError::UnverifiedSources(paths) => {
+                let names: Vec<_> = paths.iter().map(|p| p.to_string_lossy()).collect();
+                write!(
+                    f,
+                    "The plan has no recorded state for {} files: {}",
+                    paths.len(),
+                    names.join(", ")
+                )
+            }
+            MurenError::InvalidGlob(e) => write!(f, "Invalid pattern: {e}"),
+            MurenError::NoMatches(pattern) => write!(f, "No files match {pattern}"),
+            MurenError::EditorFailed(reason) => write!(f, "The editor failed: {reason}"),
+            MurenError::InvalidEdit(reason) => write!(f, "Invalid edit: {reason}"),
+            MurenError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for MurenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MurenError::InvalidRegex(e) => Some(e),
+            MurenError::InvalidPlan(e) => Some(e),
+            MurenError::InvalidGlob(e) => Some(e),
+            MurenError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MurenError {
+    fn from(e: io::Error) -> Self {
+        MurenError::Io(e)
+    }
+}
+
+impl From<regex::Error> for MurenError {
+    fn from(e: regex::Error) -> Self {
+        MurenError::InvalidRegex(e)
+    }
+}
+
+impl From<PlanError> for MurenError {
+    fn from(e: PlanError) -> Self {
+        MurenError::Plan(e)
+    }
+}
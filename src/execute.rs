@@ -0,0 +1,559 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use crate::links::Relink;
+use crate::plan::{DirChange, RenameStep};
+use crate::report::{RenameStatus, Reporter};
+
+/// How a file got to its new name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transfer {
+    /// Renamed in place by the filesystem.
+    Renamed,
+    /// Copied to another filesystem and removed from the original one.
+    Copied,
+    /// Copied to another filesystem, but the original could not be removed (for this reason).
+    CopiedKeepingSource(String),
+}
+
+/// Move a file or a directory tree to a new path.
+///
+/// When the new path is on another filesystem, the tree is copied (with permissions
+/// and timestamps), the copy is compared with the original, and only then the original
+/// is removed.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<Transfer> {
+    match fs::rename(from, to) {
+        Ok(_) => Ok(Transfer::Renamed),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => move_across_devices(from, to),
+        Err(e) => Err(e),
+    }
+}
+
+fn move_across_devices(from: &Path, to: &Path) -> io::Result<Transfer> {
+    // The copy replaces what is at the new path only once it is complete,
+    // the same way as a rename would.
+    let copy = copy_name(to);
+    let result = copy_tree(from, &copy)
+        .and_then(|_| verify_copy(from, &copy))
+        .and_then(|_| fs::rename(&copy, to));
+    if let Err(e) = result {
+        // Do not leave a partial copy behind.
+        if copy.symlink_metadata().is_ok() {
+            let _ = remove_tree(&copy);
+        }
+        return Err(e);
+    }
+    // The file is at its new name already, whatever happens to the original
+    match remove_tree(from) {
+        Ok(_) => Ok(Transfer::Copied),
+        Err(e) => Ok(Transfer::CopiedKeepingSource(e.to_string())),
+    }
+}
+
+/// A free name next to `path` for the copy in progress.
+fn copy_name(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    (0..)
+        .map(|counter| {
+            let mut name = OsString::from(".");
+            name.push(file_name);
+            name.push(format!(".muren-copy-{counter}"));
+            path.with_file_name(name)
+        })
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .unwrap()
+}
+
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = from.symlink_metadata()?;
+    if metadata.is_symlink() {
+        copy_symlink(from, to)?;
+        return Ok(());
+    } else if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        // Only now, the directory may be read-only.
+        fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    // Directories cannot be opened as files on Windows (without backup semantics)
+    if cfg!(not(unix)) && metadata.is_dir() {
+        return Ok(());
+    }
+    let times = FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::open(to)?.set_times(times)
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        format!("cannot copy symlink {}", from.display()),
+    ))
+}
+
+/// Check that the copy has the same structure and contents as the original.
+fn verify_copy(original: &Path, copy: &Path) -> io::Result<()> {
+    let metadata = original.symlink_metadata()?;
+    let copy_metadata = copy.symlink_metadata()?;
+    let matches = if metadata.is_symlink() {
+        copy_metadata.is_symlink() && fs::read_link(original)? == fs::read_link(copy)?
+    } else if metadata.is_dir() {
+        for entry in fs::read_dir(original)? {
+            let entry = entry?;
+            verify_copy(&entry.path(), &copy.join(entry.file_name()))?;
+        }
+        copy_metadata.is_dir() && fs::read_dir(original)?.count() == fs::read_dir(copy)?.count()
+    } else {
+        copy_metadata.len() == metadata.len() && has_same_contents(original, copy)?
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "the copy {} differs from the original",
+            copy.display()
+        )))
+    }
+}
+
+fn has_same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut buffer_a, mut buffer_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(b.read(&mut buffer_b)? == 0);
+        }
+        b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+fn remove_tree(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Perform the renames in order, returning the status of each file by its original name.
+///
+/// When a file could not be moved away, no other file is renamed over its name.
+/// In the atomic mode, the first failure stops the batch and all renames done so far
+/// are reverted.
+pub fn execute_plan(
+    steps: &[RenameStep],
+    atomic: bool,
+    reporter: &dyn Reporter,
+) -> HashMap<PathBuf, RenameStatus> {
+    let mut statuses = HashMap::new();
+    let mut stuck: HashSet<&Path> = HashSet::new();
+    // Original names of the files parked under a temporary name.
+    let mut parked: HashMap<&Path, &Path> = HashMap::new();
+    for (index, step) in steps.iter().enumerate() {
+        let result = if stuck.contains(step.to.as_path()) {
+            Err(String::from("target not vacated"))
+        } else {
+            move_path(&step.from, &step.to).map_err(|e| e.to_string())
+        };
+        reporter.renamed(step, &result);
+        if let Ok(Transfer::CopiedKeepingSource(e)) = &result {
+            reporter.warn(&format!(
+                "{} was copied to {}, but the original is left behind ({e}).",
+                step.from.display(),
+                step.to.display()
+            ));
+        }
+
+        let original = parked
+            .get(step.from.as_path())
+            .copied()
+            .unwrap_or(&step.from);
+        match result {
+            Err(e) => {
+                // The rollback restores the parked files in the atomic mode
+                let status = match !atomic && parked.contains_key(step.from.as_path()) {
+                    true => restore_parked(&step.from, original, e, reporter),
+                    false => RenameStatus::Failed(e),
+                };
+                statuses.insert(original.to_path_buf(), status);
+                if atomic {
+                    roll_back(&steps[..index], &parked, &mut statuses, reporter);
+                    for step in &steps[index + 1..] {
+                        let original = parked.get(step.from.as_path()).copied();
+                        statuses
+                            .entry(original.unwrap_or(&step.from).to_path_buf())
+                            .or_insert(RenameStatus::Skipped);
+                    }
+                    return statuses;
+                }
+                stuck.insert(&step.from);
+                // Back under its original name, unless it could not be moved there
+                if !matches!(statuses[original], RenameStatus::NotRestored { .. }) {
+                    stuck.insert(original);
+                }
+            }
+            Ok(_) if step.temporary => {
+                parked.insert(&step.to, &step.from);
+            }
+            Ok(_) => {
+                statuses.insert(original.to_path_buf(), RenameStatus::Renamed);
+            }
+        }
+    }
+    statuses
+}
+
+/// Move a file parked under a temporary name back to its original name after its rename failed.
+fn restore_parked(
+    parked: &Path,
+    original: &Path,
+    error: String,
+    reporter: &dyn Reporter,
+) -> RenameStatus {
+    let step = RenameStep {
+        from: original.to_path_buf(),
+        to: parked.to_path_buf(),
+        temporary: true,
+    };
+    let result = match original.symlink_metadata() {
+        Ok(_) => Err(String::from("original name taken")),
+        Err(_) => move_path(parked, original).map_err(|e| e.to_string()),
+    };
+    reporter.rolled_back(&step, &result);
+    match result {
+        Ok(_) => RenameStatus::Failed(error),
+        Err(restore_error) => RenameStatus::NotRestored {
+            left_at: parked.to_path_buf(),
+            error: format!("{error}, then {restore_error}"),
+        },
+    }
+}
+
+/// Create the directories in order, returning the changes that succeeded.
+pub fn create_dirs(dirs: &[PathBuf], reporter: &dyn Reporter) -> Vec<DirChange> {
+    change_dirs(
+        dirs,
+        DirChange::Created,
+        |dir| fs::create_dir(dir),
+        reporter,
+    )
+}
+
+/// Remove the directories in order if they are empty, returning the changes that succeeded.
+pub fn remove_dirs(dirs: &[PathBuf], reporter: &dyn Reporter) -> Vec<DirChange> {
+    change_dirs(
+        dirs,
+        DirChange::Removed,
+        |dir| fs::remove_dir(dir),
+        reporter,
+    )
+}
+
+fn change_dirs(
+    dirs: &[PathBuf],
+    change: fn(PathBuf) -> DirChange,
+    operation: fn(&Path) -> io::Result<()>,
+    reporter: &dyn Reporter,
+) -> Vec<DirChange> {
+    let mut changes = Vec::new();
+    for dir in dirs {
+        let result = operation(dir).map_err(|e| e.to_string());
+        let dir_change = change(dir.clone());
+        reporter.dir_changed(&dir_change, &result);
+        if result.is_ok() {
+            changes.push(dir_change);
+        }
+    }
+    changes
+}
+
+/// Point the links to their new targets, returning the changes that succeeded.
+pub fn relink_all(relinks: &[Relink], reporter: &dyn Reporter) -> Vec<Relink> {
+    let mut changed = Vec::new();
+    for relink in relinks {
+        let result = replace_symlink(&relink.link, &relink.new_target).map_err(|e| e.to_string());
+        reporter.relinked(relink, &result);
+        if result.is_ok() {
+            changed.push(relink.clone());
+        }
+    }
+    changed
+}
+
+#[cfg(unix)]
+fn replace_symlink(link: &Path, target: &Path) -> io::Result<()> {
+    fs::remove_file(link)?;
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn replace_symlink(link: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        format!("cannot rewrite symlink {}", link.display()),
+    ))
+}
+
+/// Revert the (successfully performed) renames in reverse order.
+fn roll_back(
+    applied: &[RenameStep],
+    parked: &HashMap<&Path, &Path>,
+    statuses: &mut HashMap<PathBuf, RenameStatus>,
+    reporter: &dyn Reporter,
+) {
+    reporter.rolling_back(applied.len());
+    for step in applied.iter().rev() {
+        let result = move_path(&step.to, &step.from).map_err(|e| e.to_string());
+        reporter.rolled_back(step, &result);
+
+        let original = parked
+            .get(step.from.as_path())
+            .copied()
+            .unwrap_or(&step.from);
+        let status = match result {
+            Ok(_) => RenameStatus::RolledBack,
+            Err(error) => RenameStatus::NotRestored {
+                left_at: step.to.clone(),
+                error,
+            },
+        };
+        // A failure to restore any step of the file is what matters.
+        if !matches!(
+            statuses.get(original),
+            Some(RenameStatus::NotRestored { .. })
+        ) {
+            statuses.insert(original.to_path_buf(), status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Silent;
+
+    #[test]
+    fn test_atomic_execution_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+
+        let steps = [
+            RenameStep {
+                from: a.clone(),
+                to: dir.path().join("x"),
+                temporary: false,
+            },
+            RenameStep {
+                from: b.clone(),
+                to: dir.path().join("missing").join("y"),
+                temporary: false,
+            },
+        ];
+        let statuses = execute_plan(&steps, true, &Silent);
+        assert_eq!(statuses[&a], RenameStatus::RolledBack);
+        assert!(matches!(statuses[&b], RenameStatus::Failed(_)));
+        assert!(a.exists());
+        assert!(b.exists());
+        assert!(!dir.path().join("x").exists());
+    }
+
+    #[test]
+    fn test_non_atomic_execution_keeps_going() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::write(&a, "a").unwrap();
+
+        let steps = [
+            RenameStep {
+                from: b.clone(),
+                to: dir.path().join("y"),
+                temporary: false,
+            },
+            RenameStep {
+                from: a.clone(),
+                to: dir.path().join("x"),
+                temporary: false,
+            },
+        ];
+        let statuses = execute_plan(&steps, false, &Silent);
+        assert!(matches!(statuses[&b], RenameStatus::Failed(_)));
+        assert_eq!(statuses[&a], RenameStatus::Renamed);
+        assert!(dir.path().join("x").exists());
+    }
+
+    #[test]
+    fn test_failed_cycle_restores_parked_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(path("a"), "a").unwrap();
+
+        // `b` disappeared, so `a` cannot take its name
+        let steps = [
+            RenameStep {
+                from: path("a"),
+                to: path(".a.muren-0"),
+                temporary: true,
+            },
+            RenameStep {
+                from: path("b"),
+                to: path("a"),
+                temporary: false,
+            },
+            RenameStep {
+                from: path(".a.muren-0"),
+                to: path("b"),
+                temporary: false,
+            },
+        ];
+        for atomic in [false, true] {
+            let statuses = execute_plan(&steps, atomic, &Silent);
+            assert!(matches!(
+                statuses[&path("a")],
+                RenameStatus::Failed(_) | RenameStatus::RolledBack
+            ));
+            assert_eq!(std::fs::read_to_string(path("a")).unwrap(), "a");
+            assert!(!path(".a.muren-0").exists());
+        }
+
+        // The original name is taken by then
+        std::fs::write(path("x"), "x").unwrap();
+        let steps = [
+            steps[0].clone(),
+            RenameStep {
+                from: path("x"),
+                to: path("a"),
+                temporary: false,
+            },
+            RenameStep {
+                from: path(".a.muren-0"),
+                to: path("missing/b"),
+                temporary: false,
+            },
+        ];
+        let statuses = execute_plan(&steps, false, &Silent);
+        assert_eq!(
+            statuses[&path("a")],
+            RenameStatus::NotRestored {
+                left_at: path(".a.muren-0"),
+                error: String::from(
+                    "No such file or directory (os error 2), then original name taken"
+                ),
+            }
+        );
+        assert_eq!(statuses[&path("x")], RenameStatus::Renamed);
+    }
+
+    #[test]
+    fn test_move_across_devices() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let tree = source_dir.path().join("tree");
+        fs::create_dir_all(tree.join("sub")).unwrap();
+        fs::write(tree.join("a.txt"), "a").unwrap();
+        fs::write(tree.join("sub").join("b.txt"), "b".repeat(100_000)).unwrap();
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(tree.join("a.txt"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let moved = target_dir.path().join("moved");
+        assert_eq!(
+            move_across_devices(&tree, &moved).unwrap(),
+            Transfer::Copied
+        );
+
+        assert!(!tree.exists());
+        assert_eq!(fs::read_to_string(moved.join("a.txt")).unwrap(), "a");
+        assert_eq!(
+            fs::read(moved.join("sub").join("b.txt")).unwrap().len(),
+            100_000
+        );
+        assert_eq!(
+            moved.join("a.txt").metadata().unwrap().modified().unwrap(),
+            modified
+        );
+    }
+
+    #[test]
+    fn test_move_across_devices_keeps_existing_target() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let tree = source_dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        fs::write(tree.join("a.txt"), "a").unwrap();
+        let existing = target_dir.path().join("existing");
+        fs::create_dir(&existing).unwrap();
+        fs::write(existing.join("kept.txt"), "kept").unwrap();
+
+        // A directory cannot replace a non-empty one
+        assert!(move_across_devices(&tree, &existing).is_err());
+        assert_eq!(
+            fs::read_to_string(existing.join("kept.txt")).unwrap(),
+            "kept"
+        );
+        assert!(tree.join("a.txt").exists());
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+
+        // A file replaces a file
+        let file = target_dir.path().join("file.txt");
+        fs::write(&file, "old").unwrap();
+        move_across_devices(&tree.join("a.txt"), &file).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "a");
+        assert!(!tree.join("a.txt").exists());
+    }
+
+    #[test]
+    fn test_verify_copy_detects_differences() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "abc").unwrap();
+        fs::write(dir.path().join("b"), "abd").unwrap();
+        fs::write(dir.path().join("c"), "abc").unwrap();
+        assert!(verify_copy(&dir.path().join("a"), &dir.path().join("b")).is_err());
+        assert!(verify_copy(&dir.path().join("a"), &dir.path().join("c")).is_ok());
+    }
+
+    #[test]
+    fn test_dir_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = [dir.path().join("a"), dir.path().join("a").join("b")];
+        assert_eq!(
+            create_dirs(&dirs, &Silent),
+            dirs.clone().map(DirChange::Created)
+        );
+        std::fs::write(dirs[1].join("file"), "").unwrap();
+
+        // Directories that are not empty are kept
+        let reversed = [dirs[1].clone(), dirs[0].clone()];
+        assert!(remove_dirs(&reversed, &Silent).is_empty());
+        std::fs::remove_file(dirs[1].join("file")).unwrap();
+        assert_eq!(remove_dirs(&reversed, &Silent).len(), 2);
+        assert!(!dirs[0].exists());
+    }
+}
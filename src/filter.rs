@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+/// Conditions that the files must meet to be renamed.
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    /// The path must match one of these (if any).
+    pub include: Vec<Regex>,
+    /// The path must not match any of these.
+    pub exclude: Vec<Regex>,
+    /// The extension must be one of these (if any), regardless of case.
+    pub extensions: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Modified after this time.
+    pub newer: Option<SystemTime>,
+    /// Modified before this time.
+    pub older: Option<SystemTime>,
+}
+
+/// Why a file was left out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exclusion {
+    NotIncluded,
+    Excluded,
+    Extension,
+    TooSmall,
+    TooLarge,
+    TooOld,
+    TooNew,
+    /// A link to follow points out of the selected tree.
+    OutsideTree,
+    /// A link to follow points nowhere.
+    Dangling,
+}
+
+impl Display for Exclusion {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Exclusion::NotIncluded => write!(f, "not matching --include"),
+            Exclusion::Excluded => write!(f, "matching --exclude"),
+            Exclusion::Extension => write!(f, "other extension than --ext"),
+            Exclusion::TooSmall => write!(f, "smaller than --min-size"),
+            Exclusion::TooLarge => write!(f, "larger than --max-size"),
+            Exclusion::TooOld => write!(f, "older than --newer"),
+            Exclusion::TooNew => write!(f, "newer than --older"),
+            Exclusion::OutsideTree => write!(f, "linking out of the tree"),
+            Exclusion::Dangling => write!(f, "dangling links"),
+        }
+    }
+}
+
+impl Filters {
+    fn needs_metadata(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer.is_some()
+            || self.older.is_some()
+    }
+
+    /// The first reason to leave the file out, if any.
+    pub fn check(&self, path: &Path) -> Option<Exclusion> {
+        let text = path.to_string_lossy();
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(&text)) {
+            return Some(Exclusion::NotIncluded);
+        }
+        if self.exclude.iter().any(|re| re.is_match(&text)) {
+            return Some(Exclusion::Excluded);
+        }
+        if !self.extensions.is_empty() {
+            let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            if !extension.is_some_and(|e| self.extensions.iter().any(|x| x.to_lowercase() == e)) {
+                return Some(Exclusion::Extension);
+            }
+        }
+        if !self.needs_metadata() {
+            return None;
+        }
+        // Missing files are not filtered out, they fail later with the other errors
+        let metadata = path.symlink_metadata().ok()?;
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min) {
+            return Some(Exclusion::TooSmall);
+        }
+        if self.max_size.is_some_and(|max| size > max) {
+            return Some(Exclusion::TooLarge);
+        }
+        let modified = metadata.modified().ok()?;
+        if self.newer.is_some_and(|time| modified <= time) {
+            return Some(Exclusion::TooOld);
+        }
+        if self.older.is_some_and(|time| modified >= time) {
+            return Some(Exclusion::TooNew);
+        }
+        None
+    }
+
+    /// Split the files into those to rename and those left out.
+    pub fn apply(&self, files: &[PathBuf]) -> (Vec<PathBuf>, Vec<(PathBuf, Exclusion)>) {
+        let mut selected = Vec::new();
+        let mut excluded = Vec::new();
+        for file in files {
+            match self.check(file) {
+                Some(exclusion) => excluded.push((file.clone(), exclusion)),
+                None => selected.push(file.clone()),
+            }
+        }
+        (selected, excluded)
+    }
+}
+
+/// How many files were left out and why, e.g. `3 files excluded (2 matching --exclude, 1 ...)`.
+pub fn exclusion_summary(excluded: &[(PathBuf, Exclusion)]) -> String {
+    let mut counts: BTreeMap<Exclusion, usize> = BTreeMap::new();
+    for (_, exclusion) in excluded {
+        *counts.entry(*exclusion).or_default() += 1;
+    }
+    let reasons: Vec<String> = counts
+        .iter()
+        .map(|(exclusion, count)| format!("{count} {exclusion}"))
+        .collect();
+    format!("{} files excluded ({})", excluded.len(), reasons.join(", "))
+}
+
+/// A size like `1500`, `10k`, `2.5M` or `1G` (in powers of 1024).
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (number, multiplier) = match text.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_lowercase() {
+                'b' => 1u64,
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                't' => 1 << 40,
+                _ => return Err(format!("unknown unit {unit:?}, use k, M, G or T")),
+            };
+            (&text[..index], multiplier)
+        }
+        _ => (text, 1),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok((number * multiplier as f64) as u64),
+        _ => Err(format!("invalid size {text:?}")),
+    }
+}
+
+/// A time given as a UTC date (`2024-04-19`, `2024-04-19 12:34[:56]`)
+/// or as an age relative to `now` (`30m`, `12h`, `7d`, `2w`).
+pub fn parse_time(text: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid time {text:?}, use e.g. 2024-04-19, 2024-04-19 12:00 or 7d");
+
+    if let Some(unit) = text.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let count: u64 = text[..text.len() - 1].parse().map_err(|_| invalid())?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => return Err(invalid()),
+        };
+        return now
+            .checked_sub(Duration::from_secs(count * seconds))
+            .ok_or_else(invalid);
+    }
+
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+    let numbers = |text: &str, separator| -> Result<Vec<i64>, String> {
+        text.split(separator)
+            .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+            .collect()
+    };
+    let (year, month, day) = match numbers(date, '-')?[..] {
+        [year, month, day] if (1..=12).contains(&month) && (1..=31).contains(&day) => {
+            (year, month, day)
+        }
+        _ => return Err(invalid()),
+    };
+    let seconds = match time.map(|time| numbers(time, ':')).transpose()?.as_deref() {
+        None => 0,
+        Some(&[hours, minutes]) => hours * 3600 + minutes * 60,
+        Some(&[hours, minutes, seconds]) => hours * 3600 + minutes * 60 + seconds,
+        Some(_) => return Err(invalid()),
+    };
+    let timestamp = days_from_civil(year, month, day) * 86400 + seconds;
+    u64::try_from(timestamp)
+        .map(|timestamp| UNIX_EPOCH + Duration::from_secs(timestamp))
+        .map_err(|_| invalid())
+}
+
+/// Days since 1970-01-01 (the inverse of the civil-from-days algorithm by Howard Hinnant).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * mp + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn at(timestamp: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(timestamp)
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size("10k"), Ok(10240));
+        assert_eq!(parse_size("2.5M"), Ok(2621440));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert!(parse_size("1x").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = at(1713530096);
+        assert_eq!(parse_time("2000-02-29", now), Ok(at(951782400)));
+        assert_eq!(parse_time("2024-04-19 12:34:56", now), Ok(at(1713530096)));
+        assert_eq!(parse_time("2024-04-19T12:34", now), Ok(at(1713530040)));
+        assert_eq!(parse_time("2d", now), Ok(at(1713530096 - 2 * 86400)));
+        assert!(parse_time("2024-13-01", now).is_err());
+        assert!(parse_time("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["a.JPG", "b.png", "c.txt", "d.jpg"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        for (file, size) in files.iter().zip([10, 100, 1000, 10000]) {
+            fs::write(file, vec![0; size]).unwrap();
+        }
+        let filters = Filters {
+            exclude: vec![Regex::new("^.*/b").unwrap()],
+            extensions: vec![String::from("jpg"), String::from("png")],
+            min_size: Some(50),
+            ..Default::default()
+        };
+
+        let (selected, excluded) = filters.apply(&files);
+        assert_eq!(selected, [dir.path().join("d.jpg")]);
+        assert_eq!(
+            excluded,
+            [
+                (dir.path().join("a.JPG"), Exclusion::TooSmall),
+                (dir.path().join("b.png"), Exclusion::Excluded),
+                (dir.path().join("c.txt"), Exclusion::Extension),
+            ]
+        );
+        assert_eq!(
+            exclusion_summary(&excluded),
+            "3 files excluded (1 matching --exclude, 1 other extension than --ext, 1 smaller than --min-size)"
+        );
+
+        let future = Filters {
+            newer: Some(SystemTime::now() + Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        assert_eq!(future.check(&files[0]), Some(Exclusion::TooOld));
+    }
+}
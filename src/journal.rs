@@ -0,0 +1,323 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{path_bytes, RenameCommand, RenameIntent};
+use crate::error::MurenError;
+use crate::links::Relink;
+use crate::plan::{DirChange, Relocation};
+
+/// One run of muren that renamed at least one file.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub id: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(with = "path_bytes")]
+    pub working_dir: PathBuf,
+    pub renames: Vec<RenameIntent>,
+    /// Directories created or removed along with the renames.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dirs: Vec<DirChange>,
+    /// Links pointed to the new names of their targets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Relink>,
+    /// Id of the batch that this one undid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<u64>,
+}
+
+/// Append-only record of all performed batches, stored as JSON lines.
+pub struct Journal {
+    pub path: PathBuf,
+}
+
+impl Journal {
+    /// `$XDG_STATE_HOME/muren/journal.jsonl`, falling back to `~/.local/state`.
+    pub fn default_location() -> Option<PathBuf> {
+        let state_home = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("state"),
+        };
+        Some(state_home.join("muren").join("journal.jsonl"))
+    }
+
+    pub fn batches(&self) -> io::Result<Vec<Batch>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        BufReader::new(file)
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    /// Store the renames performed in the current working directory as a new batch.
+    pub fn record(
+        &self,
+        renames: &[RenameIntent],
+        dirs: &[DirChange],
+        links: &[Relink],
+        reverts: Option<u64>,
+    ) -> io::Result<Batch> {
+        let id = self
+            .batches()?
+            .iter()
+            .map(|batch| batch.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let batch = Batch {
+            id,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            working_dir: env::current_dir()?,
+            renames: renames.to_vec(),
+            dirs: dirs.to_vec(),
+            links: links.to_vec(),
+            reverts,
+        };
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&batch)?)?;
+        Ok(batch)
+    }
+
+    /// The batch to undo: the chosen one, or the latest one that is not undone (or an undo).
+    pub fn batch_to_undo(&self, id: Option<u64>) -> Result<Batch, MurenError> {
+        let batches = self.batches()?;
+        let is_undone = |batch: &Batch| batches.iter().any(|b| b.reverts == Some(batch.id));
+        match id {
+            Some(id) => match batches.iter().find(|batch| batch.id == id) {
+                None => Err(MurenError::UnknownBatch(id)),
+                Some(batch) if is_undone(batch) => Err(MurenError::AlreadyUndone(id)),
+                Some(batch) => Ok(batch.clone()),
+            },
+            None => batches
+                .iter()
+                .rev()
+                .find(|batch| batch.reverts.is_none() && !is_undone(batch))
+                .cloned()
+                .ok_or(MurenError::NothingToUndo),
+        }
+    }
+}
+
+impl Display for Batch {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "#{0} {1} in {2}: {3} files renamed",
+            self.id,
+            format_timestamp(self.timestamp),
+            self.working_dir.to_string_lossy(),
+            self.renames.len()
+        )?;
+        if let Some(reverted) = self.reverts {
+            write!(f, " (undo of #{reverted})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reverse all renames of a journal batch.
+pub struct Undo {
+    pub batch: Batch,
+}
+
+impl Undo {
+    /// Paths are recorded relative to the working directory of the batch.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match env::current_dir() {
+            Ok(dir) if dir == self.batch.working_dir => path.to_path_buf(),
+            _ => self.batch.working_dir.join(path),
+        }
+    }
+}
+
+impl Undo {
+    /// The directories created by the batch, where they are now; they are removed once emptied.
+    pub fn created_dirs(&self) -> Vec<PathBuf> {
+        let relocation = Relocation::new(&self.batch.renames);
+        self.batch
+            .dirs
+            .iter()
+            .filter_map(|change| match change {
+                DirChange::Created(dir) => Some(self.resolve(&relocation.apply(dir))),
+                DirChange::Removed(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl RenameCommand for Undo {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        let current = old_name.to_path_buf();
+        Ok(self
+            .batch
+            .renames
+            .iter()
+            .find(|intent| self.resolve(&intent.new_name) == current)
+            .map(|intent| self.resolve(&intent.old_name))
+            .unwrap_or(current))
+    }
+
+    /// The files are given by the batch, not by the user.
+    ///
+    /// Files in renamed directories are now found under the new directory names,
+    /// where they get their old names back before the directories do.
+    fn suggest_renames(&self, _files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
+        let relocation = Relocation::new(&self.batch.renames);
+        Ok(self
+            .batch
+            .renames
+            .iter()
+            .rev()
+            .map(|intent| {
+                RenameIntent::new(
+                    self.resolve(&relocation.apply(&intent.new_name)),
+                    self.resolve(&relocation.apply(&intent.old_name)),
+                )
+            })
+            .collect())
+    }
+}
+
+/// UTC date and time as `YYYY-MM-DD HH:MM:SS`.
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Civil-from-days algorithm by Howard Hinnant.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {0:02}:{1:02}:{2:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intent(old: &str, new: &str) -> RenameIntent {
+        RenameIntent::new(PathBuf::from(old), PathBuf::from(new))
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1713530096), "2024-04-19 12:34:56");
+    }
+
+    #[test]
+    fn test_record_and_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal {
+            path: dir.path().join("muren").join("journal.jsonl"),
+        };
+        assert!(journal.batch_to_undo(None).is_err());
+
+        let first = journal.record(&[intent("a", "b")], &[], &[], None).unwrap();
+        let second = journal.record(&[intent("c", "d")], &[], &[], None).unwrap();
+        assert_eq!((first.id, second.id), (1, 2));
+        assert_eq!(journal.batch_to_undo(None).unwrap().id, 2);
+
+        journal
+            .record(&[intent("d", "c")], &[], &[], Some(2))
+            .unwrap();
+        assert_eq!(journal.batch_to_undo(None).unwrap().id, 1);
+        assert!(journal.batch_to_undo(Some(2)).is_err());
+        assert!(journal.batch_to_undo(Some(42)).is_err());
+        assert_eq!(journal.batches().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_undo_reverses_renames() {
+        let undo = Undo {
+            batch: Batch {
+                id: 1,
+                timestamp: 0,
+                working_dir: env::current_dir().unwrap(),
+                renames: vec![intent("a", "b"), intent("b", "c")],
+                dirs: vec![],
+                links: vec![],
+                reverts: None,
+            },
+        };
+        let intents = undo.suggest_renames(&[]).unwrap();
+        let pairs: Vec<(&Path, &Path)> = intents
+            .iter()
+            .map(|i| (i.old_name.as_path(), i.new_name.as_path()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (Path::new("c"), Path::new("b")),
+                (Path::new("b"), Path::new("a"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undo_renamed_directory() {
+        let undo = Undo {
+            batch: Batch {
+                id: 1,
+                timestamp: 0,
+                working_dir: env::current_dir().unwrap(),
+                renames: vec![intent("d/f", "d/g/f"), intent("d", "D")],
+                dirs: vec![
+                    DirChange::Created(PathBuf::from("d/g")),
+                    DirChange::Removed(PathBuf::from("e")),
+                ],
+                links: vec![],
+                reverts: None,
+            },
+        };
+        let intents = undo.suggest_renames(&[]).unwrap();
+        let pairs: Vec<(&Path, &Path)> = intents
+            .iter()
+            .map(|i| (i.old_name.as_path(), i.new_name.as_path()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (Path::new("D"), Path::new("d")),
+                (Path::new("D/g/f"), Path::new("D/f"))
+            ]
+        );
+        assert_eq!(undo.created_dirs(), [PathBuf::from("D/g")]);
+    }
+}
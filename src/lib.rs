@@ -1,143 +1,516 @@
 pub mod commands;
+pub mod comparison;
+pub mod diff;
+pub mod edit;
+pub mod error;
+pub mod execute;
 pub mod extensions;
+pub mod filter;
+pub mod journal;
+pub mod links;
+pub mod plan;
+pub mod report;
+pub mod saved_plan;
+pub mod scope;
+pub mod target_fs;
+pub mod walk;
 
-use colored::Colorize;
 use std::collections::HashSet;
-use std::fs::rename;
-use std::ops::Deref;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 extern crate unidecode;
 use crate::commands::{RenameCommand, RenameIntent};
+use crate::comparison::NameComparison;
+use crate::error::MurenError;
+use crate::execute::{create_dirs, execute_plan, relink_all, remove_dirs};
+use crate::filter::{Exclusion, Filters};
+use crate::journal::Journal;
+use crate::links::{find_relative_links, follow_links, plan_relinks, tree_roots, SymlinkPolicy};
+use crate::plan::{
+    check_names, emptied_dirs, missing_dirs, nest_in_old_dirs, normalize_new_names, plan_renames,
+    resolve_conflicts, skip_lossy_names, ConflictPolicy, DirChange, EmptyDirs,
+};
+use crate::report::{
+    status_before_execution, RenameOutcome, RenameReport, RenameStatus, Reporter, Review,
+};
+use crate::target_fs::TargetFs;
 
 pub struct Config {
     pub command: Box<dyn RenameCommand>,
     pub dry: bool,
     pub files: Vec<PathBuf>,
+    /// Which of the files to rename.
+    pub filters: Filters,
     pub auto_confirm: bool,
-    pub show_unchanged: bool,
+    /// Ask about each rename separately instead of the whole batch.
+    pub interactive: bool,
+    /// Undo the already performed renames if any of them fails.
+    pub atomic: bool,
+    /// Where to record the performed renames (if anywhere).
+    pub journal: Option<Journal>,
+    /// Id of the journal batch that this run undoes.
+    pub reverts: Option<u64>,
+    /// What to do with new names that already exist on the disk.
+    pub on_conflict: ConflictPolicy,
+    /// How to compare names when looking for collisions (detected from the files if `None`).
+    pub name_comparison: Option<NameComparison>,
+    /// Create the missing parent directories of the new names.
+    pub create_dirs: bool,
+    pub empty_dirs: EmptyDirs,
+    /// Rename the selected links or their targets.
+    pub symlinks: SymlinkPolicy,
+    /// Point the relative links in the tree to the new names of their targets.
+    pub fix_links: bool,
+    /// The filesystem on which the new names must be valid.
+    pub target_fs: TargetFs,
 }
 
-fn confirm_intents(intents: &Vec<RenameIntent>) -> bool {
-    println!("The following files will be renamed:");
-    print_intents(intents, false);
-    println!("Do you want to continue? [y/N] ");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    input.trim().to_lowercase() == "y"
+/// The files to rename: those that pass the filters, or the targets of the links among them.
+pub fn select_files(
+    files: &[PathBuf],
+    filters: &Filters,
+    symlinks: SymlinkPolicy,
+) -> (Vec<PathBuf>, Vec<(PathBuf, Exclusion)>) {
+    let (mut files, mut excluded) = filters.apply(files);
+    if symlinks == SymlinkPolicy::Target {
+        let (targets, not_followed) = follow_links(&files);
+        files = targets;
+        excluded.extend(not_followed);
+    }
+    (files, excluded)
 }
 
-/// Print all renames
-fn print_intents(intents: &Vec<RenameIntent>, show_unchanged: bool) {
-    for intent in intents {
-        if intent.is_changed() || show_unchanged {
-            println!("{}", intent);
-        }
+fn process_command(config: &Config, reporter: &dyn Reporter) -> Result<RenameReport, MurenError> {
+    let (files, excluded) = select_files(&config.files, &config.filters, config.symlinks);
+    if !excluded.is_empty() {
+        reporter.excluded(&excluded);
     }
-}
+    let suggested = config.command.suggest_renames(&files)?;
 
-fn try_rename(path: &Path, new_name: &Path) -> bool {
-    match rename(path, new_name) {
-        Ok(_) => {
-            println!(
-                "{0} {1} → {2}",
-                "✓".green(),
-                path.to_string_lossy().red(),
-                new_name.to_string_lossy().green()
-            );
-            true
-        }
-        Err(_) => {
-            eprintln!(
-                "{0} {1} → {2}",
-                "✗".red(),
-                path.to_string_lossy().red(),
-                new_name.to_string_lossy().green()
-            );
-            false
-        }
+    let comparison = config.name_comparison.unwrap_or_else(|| {
+        // The new names decide, they may be on another filesystem than the files
+        let paths: Vec<PathBuf> = suggested.iter().map(|i| i.new_name.clone()).collect();
+        NameComparison::detect(&paths)
+    });
+    let check = |suggested: &[RenameIntent]| {
+        let mut intents = suggested.to_vec();
+        normalize_new_names(&mut intents);
+        skip_lossy_names(&mut intents);
+        check_names(&mut intents, config.target_fs, &config.on_conflict);
+        nest_in_old_dirs(&mut intents);
+        resolve_conflicts(&mut intents, &config.on_conflict, comparison);
+        intents
+    };
+    let reviewed = config.interactive && !config.dry;
+    let (intents, declined) = if reviewed {
+        review_intents(suggested, reporter, check)?
+    } else {
+        (check(&suggested), HashSet::new())
+    };
+    let blocked_count = intents.iter().filter(|i| i.is_blocked()).count();
+    if blocked_count > 0 {
+        reporter.preview(&intents);
+        return Err(MurenError::Conflicts(blocked_count));
     }
-}
 
-fn process_command(
-    command: &dyn RenameCommand,
-    files: &[PathBuf],
-    dry: bool,
-    auto_confirm: bool,
-    show_unchanged: bool,
-) {
-    let intents = command.suggest_renames(files);
+    let plan = plan_renames(&intents, comparison)?;
+
+    let mut new_dirs = missing_dirs(&intents);
+    if !config.create_dirs && !new_dirs.is_empty() {
+        reporter.warn("Some new names are in missing directories, use `--mkdir` to create them.");
+        new_dirs.clear();
+    }
+    let old_dirs = plan_dir_removals(&intents, &config.empty_dirs);
+    let planned_dirs: Vec<DirChange> = new_dirs
+        .iter()
+        .cloned()
+        .map(DirChange::Created)
+        .chain(old_dirs.iter().cloned().map(DirChange::Removed))
+        .collect();
+    if !planned_dirs.is_empty() {
+        reporter.planned_dirs(&planned_dirs);
+    }
 
-    if contains_duplicates(&intents) {
-        print!("All target names are not unique!");
-        print_intents(&intents, false);
-        return;
+    let links = match config.fix_links {
+        true => {
+            // The whole walked tree, not only the directories of the renamed files
+            let searched: Vec<PathBuf> = intents
+                .iter()
+                .filter(|intent| intent.is_changed())
+                .map(|intent| intent.old_name.clone())
+                .chain(config.files.iter().cloned())
+                .collect();
+            find_relative_links(&tree_roots(&searched))
+        }
+        false => vec![],
+    };
+    let planned_relinks = plan_relinks(&links, &intents);
+    if !planned_relinks.is_empty() {
+        reporter.planned_relinks(&planned_relinks);
     }
 
-    if dry {
-        print_intents(&intents, show_unchanged);
+    let mut report = RenameReport {
+        outcomes: intents
+            .iter()
+            .map(|intent| RenameOutcome {
+                intent: intent.clone(),
+                status: if declined.contains(&intent.old_name) {
+                    RenameStatus::Skipped
+                } else {
+                    status_before_execution(intent)
+                },
+            })
+            .collect(),
+        dry: config.dry,
+    };
+
+    if config.dry {
+        reporter.preview(&intents);
     } else {
-        let confirmed = auto_confirm || {
+        let confirmed = config.auto_confirm || reviewed || {
             let changed_count = intents.iter().filter(|i| i.is_changed()).count();
-            (changed_count == 0) || confirm_intents(&intents)
+            (changed_count == 0) || reporter.confirm(&intents)?
         };
-
-        let mut renamed_count = 0;
-        if confirmed {
-            for intent in intents {
-                if intent.is_changed() {
-                    let renamed = try_rename(&intent.old_name, &intent.new_name);
-                    renamed_count += renamed as i32;
-                }
-                if show_unchanged {
-                    println!("{}", intent)
+        if !confirmed {
+            for outcome in report.outcomes.iter_mut() {
+                if outcome.status == RenameStatus::Planned {
+                    outcome.status = RenameStatus::Skipped;
                 }
             }
-            println!("{renamed_count} files renamed.");
+            return Ok(report);
+        }
+
+        let mut dir_changes = create_dirs(&new_dirs, reporter);
+        let statuses = execute_plan(&plan, config.atomic, reporter);
+        for outcome in report.outcomes.iter_mut() {
+            if let Some(status) = statuses.get(&outcome.intent.old_name) {
+                outcome.status = status.clone();
+            } else if outcome.status == RenameStatus::Planned {
+                outcome.status = RenameStatus::Skipped;
+            }
+        }
+        let renamed = report.renamed();
+
+        let rolled_back = statuses
+            .values()
+            .any(|status| *status == RenameStatus::RolledBack);
+        if rolled_back {
+            let created: Vec<PathBuf> = new_dirs.into_iter().rev().collect();
+            remove_dirs(&created, reporter);
+            dir_changes.clear();
+        } else {
+            dir_changes.extend(remove_dirs(&old_dirs, reporter));
+        }
+        // Only the links to the files that were actually renamed are changed
+        let relinks = match rolled_back {
+            true => vec![],
+            false => relink_all(&plan_relinks(&links, &renamed), reporter),
+        };
+
+        if let (Some(journal), false) = (&config.journal, renamed.is_empty()) {
+            if let Err(e) = journal.record(&renamed, &dir_changes, &relinks, config.reverts) {
+                reporter.warn(&format!("Could not write the journal: {e}"));
+            }
         }
     };
+    reporter.finished(&report);
+    Ok(report)
 }
 
-fn contains_duplicates(intents: &[RenameIntent]) -> bool {
-    let new_names: Vec<PathBuf> = intents
-        .iter()
-        .map(|intent| intent.new_name.clone())
-        .collect();
-    let mut uniq = HashSet::new();
-    !new_names.into_iter().all(move |x| uniq.insert(x))
+/// The directories to remove after the renames, deepest first.
+fn plan_dir_removals(intents: &[RenameIntent], empty_dirs: &EmptyDirs) -> Vec<PathBuf> {
+    match empty_dirs {
+        EmptyDirs::Keep => vec![],
+        EmptyDirs::Remove => emptied_dirs(intents),
+        EmptyDirs::RemoveOnly(dirs) => emptied_dirs(intents)
+            .into_iter()
+            .filter(|dir| dirs.contains(dir))
+            .collect(),
+    }
 }
 
-pub fn run(config: &Config) {
-    process_command(
-        config.command.deref(),
-        &config.files,
-        config.dry,
-        config.auto_confirm,
-        config.show_unchanged,
-    );
+/// Let the reporter accept, decline or change each rename, returning the checked intents
+/// and the declined files.
+///
+/// The reporter sees the names as they will be after the checks, which run again after each change.
+fn review_intents(
+    mut suggested: Vec<RenameIntent>,
+    reporter: &dyn Reporter,
+    check: impl Fn(&[RenameIntent]) -> Vec<RenameIntent>,
+) -> Result<(Vec<RenameIntent>, HashSet<PathBuf>), MurenError> {
+    let mut checked = check(&suggested);
+    let mut is_stale = false;
+    let mut declined = HashSet::new();
+    let mut remaining = None;
+    for index in 0..suggested.len() {
+        if !suggested[index].is_changed() {
+            continue;
+        }
+        let review = match &remaining {
+            Some(review) => Review::clone(review),
+            None => {
+                if is_stale {
+                    checked = check(&suggested);
+                    is_stale = false;
+                }
+                reporter.review(&checked[index])?
+            }
+        };
+        let intent = &mut suggested[index];
+        match review {
+            Review::Accept => {}
+            Review::AcceptAll => remaining = Some(Review::Accept),
+            Review::Rename(new_name) => {
+                intent.new_name = new_name;
+                is_stale = true;
+            }
+            Review::Decline | Review::Quit => {
+                if review == Review::Quit {
+                    remaining = Some(Review::Decline);
+                }
+                intent.new_name = intent.old_name.clone();
+                declined.insert(intent.old_name.clone());
+                is_stale = true;
+            }
+        }
+    }
+    if is_stale {
+        checked = check(&suggested);
+    }
+    Ok((checked, declined))
+}
+
+/// Suggest, check, confirm and perform the renames, describing the progress to the reporter.
+pub fn run(config: &Config, reporter: &dyn Reporter) -> Result<RenameReport, MurenError> {
+    process_command(config, reporter)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_run_reports_outcomes() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["a", "x_b"].iter().map(|n| dir.path().join(n)).collect();
+        for file in &files {
+            std::fs::write(file, "").unwrap();
+        }
+        let config = |auto_confirm| Config {
+            command: Box::new(crate::commands::Replace {
+                pattern: String::from("^.*/(x_)?"),
+                replacement: format!("{}/x_", dir.path().to_string_lossy()),
+                is_regex: true,
+            }),
+            dry: false,
+            files: files.clone(),
+            filters: Filters::default(),
+            auto_confirm,
+            interactive: false,
+            atomic: false,
+            journal: None,
+            reverts: None,
+            on_conflict: ConflictPolicy::Fail,
+            name_comparison: None,
+            create_dirs: false,
+            empty_dirs: EmptyDirs::Keep,
+            symlinks: SymlinkPolicy::Link,
+            fix_links: false,
+            target_fs: TargetFs::Posix,
+        };
+
+        // Silent reporter never confirms
+        let declined = run(&config(false), &report::Silent).unwrap();
+        assert_eq!(declined.totals().skipped, 1);
+        assert!(files[0].exists());
+
+        let report = run(&config(true), &report::Silent).unwrap();
+        let totals = report.totals();
+        assert_eq!((totals.renamed, totals.unchanged), (1, 1));
+        assert_eq!(report.renamed()[0].new_name, dir.path().join("x_a"));
+        assert!(dir.path().join("x_a").exists());
+    }
+
+    /// Gives the prepared answers in turn, remembering the reviewed intents.
+    struct Scripted(RefCell<Vec<Review>>, RefCell<Vec<RenameIntent>>);
+
+    impl Reporter for Scripted {
+        fn review(&self, intent: &RenameIntent) -> Result<Review, MurenError> {
+            self.1.borrow_mut().push(intent.clone());
+            Ok(self.0.borrow_mut().remove(0))
+        }
+    }
+
+    fn scripted(answers: Vec<Review>) -> Scripted {
+        Scripted(RefCell::new(answers), RefCell::new(vec![]))
+    }
+
+    fn batch() -> Vec<RenameIntent> {
+        ["a", "b", "c", "d"]
+            .iter()
+            .map(|n| RenameIntent::new(PathBuf::from(n), PathBuf::from(n.to_uppercase())))
+            .collect()
+    }
 
     #[test]
-    fn test_contains_duplicates() {
-        let a_to_b = RenameIntent {
-            old_name: PathBuf::from("a"),
-            new_name: PathBuf::from("b"),
+    fn test_review_intents() {
+        let review = |answers: Vec<Review>| {
+            let (batch, declined) =
+                review_intents(batch(), &scripted(answers), |intents| intents.to_vec()).unwrap();
+            let new_names: Vec<String> = batch
+                .iter()
+                .map(|i| i.new_name.to_string_lossy().to_string())
+                .collect();
+            (new_names, declined.len())
         };
-        let b_to_d = RenameIntent {
-            old_name: PathBuf::from("b"),
-            new_name: PathBuf::from("d"),
+
+        assert_eq!(
+            review(vec![
+                Review::Accept,
+                Review::Decline,
+                Review::Rename(PathBuf::from("e")),
+                Review::Quit
+            ]),
+            (vec!["A".into(), "b".into(), "e".into(), "d".into()], 2)
+        );
+        assert_eq!(
+            review(vec![Review::Decline, Review::AcceptAll]),
+            (vec!["a".into(), "B".into(), "C".into(), "D".into()], 1)
+        );
+        assert_eq!(
+            review(vec![Review::Quit]),
+            (vec!["a".into(), "b".into(), "c".into(), "d".into()], 4)
+        );
+    }
+
+    #[test]
+    fn test_review_checked_names() {
+        let policy = ConflictPolicy::AutoSuffix(String::from("{n}"));
+        let check = |intents: &[RenameIntent]| {
+            let mut checked = intents.to_vec();
+            resolve_conflicts(&mut checked, &policy, NameComparison::Exact);
+            checked
         };
-        let c_to_d = RenameIntent {
-            old_name: PathBuf::from("c"),
-            new_name: PathBuf::from("d"),
+        let reporter = scripted(vec![
+            Review::Rename(PathBuf::from("B")),
+            Review::Accept,
+            Review::Quit,
+        ]);
+        let (checked, _) = review_intents(batch(), &reporter, check).unwrap();
+
+        // The edited name is checked before the next review
+        let seen = reporter.1.borrow();
+        assert_eq!(seen[1].new_name, PathBuf::from("B2"));
+        assert_eq!(checked[1].new_name, PathBuf::from("B2"));
+        assert_eq!(
+            checked[1].resolution,
+            Some(crate::commands::Resolution::Suffixed)
+        );
+    }
+
+    #[test]
+    fn test_run_creates_and_removes_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        std::fs::create_dir(path("old")).unwrap();
+        std::fs::write(path("old/a"), "").unwrap();
+        let journal = Journal {
+            path: path("journal.jsonl"),
+        };
+        let config = Config {
+            command: Box::new(crate::commands::Replace {
+                pattern: String::from("old/"),
+                replacement: String::from("new/sub/"),
+                is_regex: false,
+            }),
+            dry: false,
+            files: vec![path("old/a")],
+            filters: Filters::default(),
+            auto_confirm: true,
+            interactive: false,
+            atomic: false,
+            journal: Some(Journal {
+                path: journal.path.clone(),
+            }),
+            reverts: None,
+            on_conflict: ConflictPolicy::Fail,
+            name_comparison: None,
+            create_dirs: true,
+            empty_dirs: EmptyDirs::Remove,
+            symlinks: SymlinkPolicy::Link,
+            fix_links: false,
+            target_fs: TargetFs::Posix,
         };
+        run(&config, &report::Silent).unwrap();
+        assert!(path("new/sub/a").exists());
+        assert!(!path("old").exists());
+
+        let batch = journal.batch_to_undo(None).unwrap();
+        assert_eq!(
+            batch.dirs,
+            [
+                DirChange::Created(path("new")),
+                DirChange::Created(path("new/sub")),
+                DirChange::Removed(path("old")),
+            ]
+        );
 
-        assert!(contains_duplicates(&vec![b_to_d, c_to_d.clone()]));
-        assert!(!contains_duplicates(&vec![a_to_b, c_to_d]));
-        assert!(!contains_duplicates(&Vec::new()));
+        let undo = crate::journal::Undo { batch };
+        let config = Config {
+            empty_dirs: EmptyDirs::RemoveOnly(undo.created_dirs()),
+            command: Box::new(undo),
+            reverts: Some(1),
+            ..config
+        };
+        run(&config, &report::Silent).unwrap();
+        assert!(path("old/a").exists());
+        assert!(!path("new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_fixes_links_in_sibling_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        std::fs::create_dir_all(path("tree/photos")).unwrap();
+        std::fs::create_dir_all(path("tree/docs")).unwrap();
+        std::fs::write(path("tree/photos/a.jpg"), "").unwrap();
+        std::os::unix::fs::symlink("../photos/a.jpg", path("tree/docs/photo")).unwrap();
+        let options = crate::walk::WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let config = Config {
+            command: Box::new(crate::commands::Replace {
+                pattern: String::from("a.jpg"),
+                replacement: String::from("A.JPG"),
+                is_regex: false,
+            }),
+            dry: false,
+            files: crate::walk::collect_files(&[path("tree")], &options).unwrap(),
+            filters: Filters {
+                extensions: vec![String::from("jpg")],
+                ..Default::default()
+            },
+            auto_confirm: true,
+            interactive: false,
+            atomic: false,
+            journal: None,
+            reverts: None,
+            on_conflict: ConflictPolicy::Fail,
+            name_comparison: None,
+            create_dirs: false,
+            empty_dirs: EmptyDirs::Keep,
+            symlinks: SymlinkPolicy::Link,
+            fix_links: true,
+            target_fs: TargetFs::Posix,
+        };
+        run(&config, &report::Silent).unwrap();
+        assert!(path("tree/photos/A.JPG").exists());
+        assert_eq!(
+            std::fs::read_link(path("tree/docs/photo")).unwrap(),
+            std::path::Path::new("../photos/A.JPG")
+        );
     }
 }
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{path_bytes, RenameIntent};
+use crate::filter::Exclusion;
+use crate::plan::Relocation;
+
+/// What to rename when a selected file is a symbolic link.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SymlinkPolicy {
+    /// The link itself.
+    #[default]
+    Link,
+    /// The file or directory it points to, if it is in the selected tree.
+    Target,
+}
+
+/// A relative symbolic link found in the tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symlink {
+    pub path: PathBuf,
+    pub target: PathBuf,
+}
+
+/// A relative symbolic link pointed to the old name of a renamed file (or was moved itself).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Relink {
+    /// Where the link is after the renames.
+    #[serde(with = "path_bytes")]
+    pub link: PathBuf,
+    #[serde(with = "path_bytes")]
+    pub old_target: PathBuf,
+    #[serde(with = "path_bytes")]
+    pub new_target: PathBuf,
+}
+
+/// The directories spanned by the files: the directories themselves and the parents of the others.
+pub fn tree_roots(files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = files
+        .iter()
+        .map(|file| match file.symlink_metadata() {
+            Ok(metadata) if metadata.is_dir() => normalize(file),
+            _ => normalize(file.parent().unwrap_or(Path::new(""))),
+        })
+        .collect();
+    roots.sort_by_key(|root| root.components().count());
+    let mut topmost: Vec<PathBuf> = Vec::new();
+    for root in roots {
+        if !topmost.iter().any(|top| root.starts_with(top)) {
+            topmost.push(root);
+        }
+    }
+    topmost
+}
+
+/// Replace the symbolic links among the files by their targets.
+///
+/// Links pointing out of the tree spanned by the files, or nowhere, are left out.
+pub fn follow_links(files: &[PathBuf]) -> (Vec<PathBuf>, Vec<(PathBuf, Exclusion)>) {
+    let roots: Vec<PathBuf> = tree_roots(files)
+        .iter()
+        .filter_map(|root| fs::canonicalize(as_dir(root)).ok())
+        .collect();
+    let mut seen: HashSet<PathBuf> = files.iter().map(|file| normalize(file)).collect();
+    let mut selected = Vec::new();
+    let mut excluded = Vec::new();
+    for file in files {
+        let Ok(target) = fs::read_link(file) else {
+            selected.push(file.clone());
+            continue;
+        };
+        let target = normalize(&file.parent().unwrap_or(Path::new("")).join(target));
+        match fs::canonicalize(&target) {
+            Err(_) => excluded.push((file.clone(), Exclusion::Dangling)),
+            Ok(canonical) if !roots.iter().any(|root| canonical.starts_with(root)) => {
+                excluded.push((file.clone(), Exclusion::OutsideTree))
+            }
+            // A target selected on its own is renamed only once
+            Ok(_) => {
+                if seen.insert(target.clone()) {
+                    selected.push(target);
+                }
+            }
+        }
+    }
+    (selected, excluded)
+}
+
+/// The relative symbolic links in the trees (without following any links).
+pub fn find_relative_links(roots: &[PathBuf]) -> Vec<Symlink> {
+    roots
+        .iter()
+        .flat_map(|root| {
+            WalkBuilder::new(as_dir(root))
+                .standard_filters(false)
+                .build()
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path_is_symlink())
+        .filter_map(|entry| {
+            let target = fs::read_link(entry.path()).ok()?;
+            target.is_relative().then(|| Symlink {
+                path: normalize(entry.path()),
+                target,
+            })
+        })
+        .collect()
+}
+
+/// The new targets of the links that would dangle after the renames.
+pub fn plan_relinks(links: &[Symlink], renames: &[RenameIntent]) -> Vec<Relink> {
+    let renames: Vec<RenameIntent> = renames
+        .iter()
+        .filter(|intent| intent.is_changed())
+        .map(|intent| RenameIntent::new(normalize(&intent.old_name), normalize(&intent.new_name)))
+        .collect();
+    let relocation = Relocation::new(&renames);
+    let new_names: HashMap<&Path, &Path> = renames
+        .iter()
+        .map(|intent| (intent.old_name.as_path(), intent.new_name.as_path()))
+        .collect();
+    let moved = |path: &Path| relocation.apply(new_names.get(path).copied().unwrap_or(path));
+    links
+        .iter()
+        .filter_map(|link| {
+            let target = normalize(&link.path.parent()?.join(&link.target));
+            let (new_link, new_target) = (moved(&link.path), moved(&target));
+            if new_link == link.path && new_target == target {
+                return None;
+            }
+            let new_target = relative_path(new_link.parent()?, &new_target)?;
+            (new_target != link.target).then(|| Relink {
+                link: new_link,
+                old_target: link.target.clone(),
+                new_target,
+            })
+        })
+        .collect()
+}
+
+/// The path without `.` components and with `..` applied where possible, without touching the disk.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The relative path from the directory to the path (both normalized), if there is one.
+fn relative_path(from_dir: &Path, to: &Path) -> Option<PathBuf> {
+    if from_dir.is_absolute() != to.is_absolute() {
+        return None;
+    }
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    if from[common..].contains(&Component::ParentDir) {
+        return None;
+    }
+    let mut path: PathBuf = from[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    path.extend(&to[common..]);
+    Some(match path.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => path,
+    })
+}
+
+/// The working directory is `""` for paths, but `.` for the filesystem.
+fn as_dir(root: &Path) -> &Path {
+    match root.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => root,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intent(old: &str, new: &str) -> RenameIntent {
+        RenameIntent::new(PathBuf::from(old), PathBuf::from(new))
+    }
+
+    fn link(path: &str, target: &str) -> Symlink {
+        Symlink {
+            path: PathBuf::from(path),
+            target: PathBuf::from(target),
+        }
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let relative = |from: &str, to: &str| relative_path(Path::new(from), Path::new(to));
+        assert_eq!(relative("a/b", "a/c/d"), Some(PathBuf::from("../c/d")));
+        assert_eq!(relative("", "a"), Some(PathBuf::from("a")));
+        assert_eq!(relative("a", "a"), Some(PathBuf::from(".")));
+        assert_eq!(relative("../x", "a"), None);
+        assert_eq!(normalize(Path::new("./a/../b/./c")), PathBuf::from("b/c"));
+    }
+
+    #[test]
+    fn test_plan_relinks() {
+        let links = [
+            link("docs/photo", "../photos/a.jpg"),
+            link("photos/latest", "a.jpg"),
+            link("photos/other", "b.jpg"),
+            link("moved", "photos/b.jpg"),
+        ];
+        let renames = [
+            intent("photos/a.jpg", "photos/A.jpg"),
+            intent("moved", "docs/moved"),
+            intent("photos", "Photos"),
+        ];
+        let relinks = plan_relinks(&links, &renames);
+        let relinks: Vec<(&str, &str)> = relinks
+            .iter()
+            .map(|relink| {
+                (
+                    relink.link.to_str().unwrap(),
+                    relink.new_target.to_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            relinks,
+            [
+                ("docs/photo", "../Photos/A.jpg"),
+                ("Photos/latest", "A.jpg"),
+                ("docs/moved", "../Photos/b.jpg"),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        fs::write(tree.join("a"), "").unwrap();
+        fs::write(dir.path().join("outside"), "").unwrap();
+        std::os::unix::fs::symlink("a", tree.join("to_a")).unwrap();
+        std::os::unix::fs::symlink("../outside", tree.join("to_outside")).unwrap();
+        std::os::unix::fs::symlink("missing", tree.join("to_missing")).unwrap();
+
+        let files = ["to_a", "to_outside", "to_missing", "a"].map(|name| tree.join(name));
+        let (selected, excluded) = follow_links(&files);
+        assert_eq!(selected, [tree.join("a")]);
+        assert_eq!(
+            excluded,
+            [
+                (tree.join("to_outside"), Exclusion::OutsideTree),
+                (tree.join("to_missing"), Exclusion::Dangling),
+            ]
+        );
+        assert_eq!(find_relative_links(&[tree]).len(), 3);
+    }
+}
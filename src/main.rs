@@ -1,31 +1,271 @@
-use std::{env, path::PathBuf};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+use std::{env, path::PathBuf, process::exit};
 
 use clap::{arg, command, value_parser, Arg, ArgAction, ArgMatches, Command};
+use regex::Regex;
 
 use muren::commands::{
     ChangeCase, FixExtension, Normalize, Prefix, Remove, RenameCommand, Replace, SetExtension,
 };
+use muren::comparison::NameComparison;
+use muren::diff::Granularity;
+use muren::edit::Edit;
+use muren::error::MurenError;
+use muren::filter::{exclusion_summary, parse_size, parse_time, Filters};
+use muren::journal::{Journal, Undo};
+use muren::links::SymlinkPolicy;
+use muren::plan::{ConflictPolicy, EmptyDirs};
+use muren::report::{OutputFormat, Reporter};
+use muren::saved_plan::{Apply, SavedPlan};
+use muren::scope::{Scope, Scoped};
+use muren::target_fs::TargetFs;
+use muren::walk::{collect_files, read_paths, FileType, WalkOptions};
 use muren::{run, Config};
 
-fn parse_config(matches: &ArgMatches) -> Config {
-    let command = extract_command(matches);
-    let files_args = matches.subcommand().unwrap().1.get_many::<PathBuf>("path");
-    let files: Vec<PathBuf> = match files_args {
-        Some(args) => args.cloned().collect(),
-        None => vec![],
+use crate::terminal::{FormattedReporter, Input, TerminalReporter};
+
+mod terminal;
+#[cfg(feature = "tui")]
+mod tui;
+
+const TARGET_FILESYSTEMS: [&str; 6] = ["posix", "windows", "fat32", "exfat", "iso9660", "macos"];
+
+fn parse_config(matches: &ArgMatches) -> Result<Config, MurenError> {
+    let journal = if matches.get_flag("no-journal") {
+        None
+    } else {
+        Journal::default_location().map(|path| Journal { path })
+    };
+    let on_conflict = match matches.get_one::<String>("on-conflict").map(String::as_str) {
+        Some("skip") => ConflictPolicy::Skip,
+        Some("overwrite") => ConflictPolicy::Overwrite,
+        Some("auto-suffix") => {
+            ConflictPolicy::AutoSuffix(matches.get_one::<String>("suffix").unwrap().clone())
+        }
+        _ => ConflictPolicy::Fail,
+    };
+    let name_comparison = match matches.get_one::<String>("collisions").map(String::as_str) {
+        Some("exact") => Some(NameComparison::Exact),
+        Some("normalized") => Some(NameComparison::Normalized),
+        Some("case-insensitive") => Some(NameComparison::CaseInsensitive),
+        _ => None,
+    };
+    let files = extract_files(matches)?;
+    let filters = extract_filters(matches);
+    let mut empty_dirs = match matches.get_flag("remove-empty") {
+        true => EmptyDirs::Remove,
+        false => EmptyDirs::Keep,
     };
-    Config {
+    let mut fix_links = matches.get_flag("fix-links");
+    let (command, reverts): (Box<dyn RenameCommand>, Option<u64>) = match matches.subcommand() {
+        Some(("undo", undo_matches)) => {
+            let batch = journal
+                .as_ref()
+                .ok_or(MurenError::JournalUnavailable)?
+                .batch_to_undo(undo_matches.get_one::<u64>("batch").copied())?;
+            let id = batch.id;
+            let undo = Undo { batch };
+            // Only what the batch created goes away, the removed directories are recreated
+            empty_dirs = EmptyDirs::RemoveOnly(undo.created_dirs());
+            // The links get their old targets back
+            fix_links |= !undo.batch.links.is_empty();
+            (Box::new(undo), Some(id))
+        }
+        Some(("apply", apply_matches)) => {
+            let plan = SavedPlan::load(apply_matches.get_one::<PathBuf>("plan").unwrap())?;
+            (Box::new(Apply { plan }), None)
+        }
+        #[cfg(feature = "tui")]
+        Some(("tui", _)) => {
+            // The same files as renamed in the end
+            let (selected, _) = muren::select_files(&files, &filters, symlink_policy(matches));
+            let intents = tui::choose_renames(
+                &selected,
+                extract_scope(matches),
+                &on_conflict,
+                name_comparison,
+                target_fs(matches.get_one::<String>("target-fs").unwrap()),
+            )?;
+            (Box::new(tui::Chosen { intents }), None)
+        }
+        _ => (scoped_command(matches)?, None),
+    };
+    Ok(Config {
         command,
         dry: matches.get_flag("dry"),
         files,
-        auto_confirm: matches.get_flag("yes"),
-        show_unchanged: matches.get_flag("unchanged"),
+        filters,
+        // Committing the renames in the TUI is the confirmation.
+        auto_confirm: matches.get_flag("yes") || matches.subcommand_name() == Some("tui"),
+        interactive: matches.get_flag("interactive"),
+        atomic: matches.get_flag("atomic"),
+        journal,
+        reverts,
+        on_conflict,
+        name_comparison,
+        create_dirs: matches.get_flag("mkdir") || reverts.is_some(),
+        empty_dirs,
+        symlinks: symlink_policy(matches),
+        fix_links,
+        target_fs: target_fs(matches.get_one::<String>("target-fs").unwrap()),
+    })
+}
+
+fn symlink_policy(matches: &ArgMatches) -> SymlinkPolicy {
+    match matches.get_one::<String>("symlinks").map(String::as_str) {
+        Some("target") => SymlinkPolicy::Target,
+        _ => SymlinkPolicy::Link,
+    }
+}
+
+/// The filesystem named in `--target-fs` or `normalize --for`.
+fn target_fs(name: &str) -> TargetFs {
+    match name {
+        "windows" => TargetFs::Windows,
+        "fat32" => TargetFs::Fat32,
+        "exfat" => TargetFs::Exfat,
+        "iso9660" => TargetFs::Iso9660,
+        "macos" => TargetFs::Macos,
+        _ => TargetFs::Posix,
+    }
+}
+
+/// The paths given as arguments.
+fn path_args(matches: &ArgMatches) -> Vec<PathBuf> {
+    matches
+        .subcommand()
+        .and_then(|(_, sub_matches)| sub_matches.try_get_many::<PathBuf>("path").ok().flatten())
+        .map(|args| args.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Whether the paths are read from stdin (`-` or `--from-stdin`).
+fn reads_stdin(matches: &ArgMatches) -> bool {
+    matches.get_flag("from-stdin") || path_args(matches).iter().any(|path| path == Path::new("-"))
+}
+
+fn extract_files(matches: &ArgMatches) -> Result<Vec<PathBuf>, MurenError> {
+    let paths: Vec<PathBuf> = path_args(matches)
+        .into_iter()
+        .filter(|path| path != Path::new("-"))
+        .collect();
+    let options = WalkOptions {
+        recursive: matches.get_flag("recursive"),
+        max_depth: matches.get_one::<usize>("max-depth").copied(),
+        hidden: matches.get_flag("hidden"),
+        file_types: matches
+            .get_many::<String>("type")
+            .unwrap_or_default()
+            .map(|file_type| match file_type.as_str() {
+                "dir" => FileType::Dir,
+                "symlink" => FileType::Symlink,
+                _ => FileType::File,
+            })
+            .collect(),
+        respect_ignore: matches.get_flag("gitignore"),
+        glob_case_insensitive: matches.get_flag("glob-ignore-case"),
+        literal: false,
+    };
+    let mut files = collect_files(&paths, &options)?;
+    if reads_stdin(matches) {
+        let stdin_paths = read_paths(io::stdin().lock(), matches.get_flag("null"))?;
+        // The names come from other tools, no patterns in them
+        let options = WalkOptions {
+            literal: true,
+            ..options
+        };
+        files.extend(collect_files(&stdin_paths, &options)?);
+    }
+    Ok(files)
+}
+
+fn extract_filters(matches: &ArgMatches) -> Filters {
+    Filters {
+        include: matches
+            .get_many::<Regex>("include")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        exclude: matches
+            .get_many::<Regex>("exclude")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        extensions: matches
+            .get_many::<String>("ext")
+            .unwrap_or_default()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect(),
+        min_size: matches.get_one::<u64>("min-size").copied(),
+        max_size: matches.get_one::<u64>("max-size").copied(),
+        newer: matches.get_one::<SystemTime>("newer").copied(),
+        older: matches.get_one::<SystemTime>("older").copied(),
     }
 }
 
-fn extract_command(args_matches: &ArgMatches) -> Box<dyn RenameCommand> {
-    match args_matches.subcommand() {
-        None => panic!("No command provided"),
+/// Write the suggested renames as JSON to stdout, to be applied later.
+fn print_plan(matches: &ArgMatches) -> Result<(), MurenError> {
+    let command = scoped_command(matches)?;
+    // The same files as renamed by the other commands
+    let (files, excluded) = muren::select_files(
+        &extract_files(matches)?,
+        &extract_filters(matches),
+        symlink_policy(matches),
+    );
+    if !excluded.is_empty() {
+        eprintln!("{}", exclusion_summary(&excluded));
+    }
+    let intents = command.suggest_renames(&files)?;
+    let plan = SavedPlan::new(intents)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&plan).map_err(std::io::Error::from)?
+    );
+    Ok(())
+}
+
+fn print_history(matches: &ArgMatches) -> Result<(), MurenError> {
+    let path = Journal::default_location().ok_or(MurenError::JournalUnavailable)?;
+    let batches = (Journal { path }).batches()?;
+    if batches.is_empty() {
+        println!("No renames recorded yet.");
+    }
+    for batch in &batches {
+        match batches.iter().find(|b| b.reverts == Some(batch.id)) {
+            Some(undo) => println!("{batch} [undone by #{}]", undo.id),
+            None => println!("{batch}"),
+        }
+        if matches.get_flag("verbose") {
+            for intent in &batch.renames {
+                println!("    {intent}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn extract_scope(matches: &ArgMatches) -> Scope {
+    match matches.get_one::<String>("scope").map(String::as_str) {
+        Some("stem") => Scope::Stem,
+        Some("extension") => Scope::Extension,
+        Some("full") => Scope::Full,
+        _ => Scope::Basename,
+    }
+}
+
+/// The command applied to the part of the paths chosen by `--scope`.
+fn scoped_command(matches: &ArgMatches) -> Result<Box<dyn RenameCommand>, MurenError> {
+    Ok(Box::new(Scoped {
+        command: extract_command(matches)?,
+        scope: extract_scope(matches),
+    }))
+}
+
+fn extract_command(args_matches: &ArgMatches) -> Result<Box<dyn RenameCommand>, MurenError> {
+    Ok(match args_matches.subcommand() {
+        None => return Err(MurenError::NoCommand),
         Some((m, matches)) => match m {
             "set-ext" => Box::new(SetExtension {
                 extension: matches.get_one::<String>("extension").unwrap().clone(),
@@ -33,7 +273,9 @@ fn extract_command(args_matches: &ArgMatches) -> Box<dyn RenameCommand> {
             "remove" => Box::new(Remove {
                 pattern: matches.get_one::<String>("pattern").unwrap().clone(),
             }),
-            "normalize" => Box::new(Normalize),
+            "normalize" => Box::new(Normalize {
+                target: matches.get_one::<String>("for").map(|name| target_fs(name)),
+            }),
             "fix-ext" => Box::new(FixExtension {
                 append: matches.get_flag("append"),
             }),
@@ -48,16 +290,125 @@ fn extract_command(args_matches: &ArgMatches) -> Box<dyn RenameCommand> {
             "change-case" => Box::new(ChangeCase {
                 upper: matches.get_flag("upper"),
             }),
-            _ => panic!("Unknown command"),
+            "edit" => Box::new(Edit::from_env()),
+            other => return Err(MurenError::UnknownCommand(other.to_string())),
         },
-    }
+    })
 }
 
-fn create_cli_command() -> Command {
+/// The commands suggesting new names for the given files.
+fn rename_subcommands() -> Vec<Command> {
     let path_arg = Arg::new("path")
         .action(ArgAction::Append)
         .value_parser(value_parser!(PathBuf));
 
+    vec![
+        Command::new("set-ext")
+            .about("Change extension")
+            .arg(
+                Arg::new("extension")
+                    .help("Extension to set (dot excluding)")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(String))
+                    .required(true),
+            )
+            .arg(path_arg.clone()),
+        Command::new("prefix")
+            .about("Prefix with string")
+            .arg(
+                Arg::new("prefix")
+                    .help("the prefix to prepend to the name")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(String))
+                    .required(true),
+            )
+            .arg(path_arg.clone()),
+        Command::new("replace")
+            .about("Replace parts of the name")
+            .arg(
+                Arg::new("pattern")
+                    .help("Pattern to match")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(String))
+                    .required(true),
+            )
+            .arg(
+                Arg::new("replacement")
+                    .help("Pattern to match")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(String))
+                    .required(true),
+            )
+            .arg(
+                arg!(
+                    -R --regex ... "The pattern is a regex"
+                )
+                .action(clap::ArgAction::SetTrue),
+            )
+            .arg(path_arg.clone()),
+        Command::new("normalize")
+            .about("Convert names to reasonable ASCII.")
+            .arg(
+                Arg::new("for")
+                    .long("for")
+                    .help("Also fix the names that are not valid on this filesystem")
+                    .action(ArgAction::Set)
+                    .value_parser(TARGET_FILESYSTEMS),
+            )
+            .arg(path_arg.clone()),
+        Command::new("fix-ext")
+            .about("Fix extension according to the file contents.")
+            .arg(path_arg.clone())
+            .arg(
+                arg!(
+                    -a --append ... "Append instead of replacing."
+                )
+                .action(clap::ArgAction::SetTrue),
+            ),
+        Command::new("remove")
+            .about("Remove part of a name from all files.")
+            .arg(
+                Arg::new("pattern")
+                    .help("The string to remove.")
+                    .action(ArgAction::Set)
+                    .value_parser(value_parser!(String))
+                    .required(true),
+            )
+            .arg(path_arg.clone()),
+        Command::new("change-case")
+            .about("Change case of all files.")
+            .arg(path_arg.clone())
+            .arg(
+                arg!(
+                    -U --upper ... "Upper case (default: false)."
+                )
+                .action(clap::ArgAction::SetTrue),
+            ),
+        Command::new("edit")
+            .about("Edit the full paths in $VISUAL or $EDITOR.")
+            .arg(path_arg.clone()),
+    ]
+}
+
+#[cfg(feature = "tui")]
+fn tui_subcommand() -> Option<Command> {
+    Some(
+        Command::new("tui")
+            .about("Build the renames in a full-screen preview.")
+            .arg(
+                Arg::new("path")
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(PathBuf)),
+            ),
+    )
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui_subcommand() -> Option<Command> {
+    None
+}
+
+fn create_cli_command() -> Command {
     command!()
         .about("(mu)ltiple (ren)ames")
         .arg_required_else_help(true)
@@ -82,90 +433,271 @@ fn create_cli_command() -> Command {
             .global(true)
             .action(clap::ArgAction::SetTrue),
         )
-        .subcommand(
-            Command::new("set-ext")
-                .about("Change extension")
-                .arg(
-                    Arg::new("extension")
-                        .help("Extension to set (dot excluding)")
-                        .action(ArgAction::Set)
-                        .value_parser(value_parser!(String))
-                        .required(true),
-                )
-                .arg(path_arg.clone()),
+        .arg(
+            arg!(
+                -i --interactive ... "Ask about each rename separately"
+            )
+            .global(true)
+            .conflicts_with("yes")
+            .action(clap::ArgAction::SetTrue),
         )
-        .subcommand(
-            Command::new("prefix")
-                .about("Prefix with string")
-                .arg(
-                    Arg::new("prefix")
-                        .help("the prefix to prepend to the name")
-                        .action(ArgAction::Set)
-                        .value_parser(value_parser!(String))
-                        .required(true),
-                )
-                .arg(path_arg.clone()),
+        .arg(
+            arg!(
+                --atomic ... "Roll back all renames if any of them fails"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
         )
-        .subcommand(
-            Command::new("replace")
-                .about("Replace parts of the name")
-                .arg(
-                    Arg::new("pattern")
-                        .help("Pattern to match")
-                        .action(ArgAction::Set)
-                        .value_parser(value_parser!(String))
-                        .required(true),
-                )
-                .arg(
-                    Arg::new("replacement")
-                        .help("Pattern to match")
-                        .action(ArgAction::Set)
-                        .value_parser(value_parser!(String))
-                        .required(true),
-                )
-                .arg(
-                    arg!(
-                        -R --regex ... "The pattern is a regex"
-                    )
-                    .action(clap::ArgAction::SetTrue),
-                )
-                .arg(path_arg.clone()),
+        .arg(
+            arg!(
+                --"no-journal" ... "Do not record the renames for a later undo"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("on-conflict")
+                .long("on-conflict")
+                .help("What to do when a new name already exists")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["fail", "skip", "overwrite", "auto-suffix"])
+                .default_value("fail"),
+        )
+        .arg(
+            Arg::new("suffix")
+                .long("suffix")
+                .help("Suffix for `--on-conflict auto-suffix`, {n} is replaced by a number")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(String))
+                .default_value(" ({n})"),
+        )
+        .arg(
+            Arg::new("collisions")
+                .long("collisions")
+                .help("How to compare names when looking for collisions")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["auto", "exact", "normalized", "case-insensitive"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("target-fs")
+                .long("target-fs")
+                .help("Refuse new names that are not valid on this filesystem")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(TARGET_FILESYSTEMS)
+                .default_value("posix"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("How to write the renames and their results")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["human", "json", "ndjson", "tsv"])
+                .default_value("human"),
+        )
+        .arg(
+            arg!(
+                -r --recursive ... "Rename the contents of the given directories"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .help("How deep to descend with `--recursive`")
+                .global(true)
+                .requires("recursive")
+                .action(ArgAction::Set)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(
+                --hidden ... "Include hidden files with `--recursive`"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(
+                --gitignore ... "Skip files ignored by .gitignore and .ignore files"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(
+                --"glob-ignore-case" ... "Match the patterns in paths regardless of case"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("type")
+                .long("type")
+                .help("Rename only these types of files")
+                .global(true)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(["file", "dir", "symlink"]),
+        )
+        .arg(
+            arg!(--mkdir "Create the missing directories of the new names")
+                .visible_alias("parents")
+                .global(true)
+                .action(ArgAction::SetTrue),
         )
+        .arg(
+            arg!(--"remove-empty" "Remove the directories left empty by the renames")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("symlinks")
+                .long("symlinks")
+                .help("Rename the symbolic links, or the files they point to (within the selected tree)")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["link", "target"])
+                .default_value("link"),
+        )
+        .arg(
+            arg!(--"fix-links" "Point the relative symbolic links in the tree to the new names of their targets")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"from-stdin" "Read the paths from stdin, one per line (also `-` as a path)")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(-'0' --null "Separate the paths read from stdin by NUL characters (as `find -print0`)")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .help("Rename only the files whose path matches the regex")
+                .global(true)
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| Regex::new(s).map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .help("Do not rename the files whose path matches the regex")
+                .global(true)
+                .action(ArgAction::Append)
+                .value_parser(|s: &str| Regex::new(s).map_err(|e| e.to_string())),
+        )
+        .arg(
+            Arg::new("ext")
+                .long("ext")
+                .help("Rename only the files with these extensions (e.g. jpg,png)")
+                .global(true)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("min-size")
+                .long("min-size")
+                .help("Rename only the files of at least this size (e.g. 10k, 2M)")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .help("Rename only the files of at most this size")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(parse_size),
+        )
+        .arg(
+            Arg::new("newer")
+                .long("newer")
+                .help("Rename only the files modified after this date or age (e.g. 2024-04-19, 7d)")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(|s: &str| parse_time(s, SystemTime::now())),
+        )
+        .arg(
+            Arg::new("older")
+                .long("older")
+                .help("Rename only the files modified before this date or age")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(|s: &str| parse_time(s, SystemTime::now())),
+        )
+        .arg(
+            Arg::new("scope")
+                .long("scope")
+                .help("The part of the paths to rename")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["basename", "stem", "extension", "full"])
+                .default_value("basename"),
+        )
+        .arg(
+            Arg::new("diff")
+                .long("diff")
+                .help("Highlight the changed parts of the names")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_parser(["off", "chars", "words"])
+                .default_value("chars"),
+        )
+        .arg(
+            arg!(
+                --columns ... "Align the old and new names in two columns"
+            )
+            .global(true)
+            .action(clap::ArgAction::SetTrue),
+        )
+        .subcommands(rename_subcommands())
+        .subcommands(tui_subcommand())
         .subcommand(
-            Command::new("normalize")
-                .about("Convert names to reasonable ASCII.")
-                .arg(path_arg.clone()),
+            Command::new("plan")
+                .about("Save the renames suggested by a command as JSON, to be applied later.")
+                .subcommand_required(true)
+                .subcommands(rename_subcommands()),
         )
         .subcommand(
-            Command::new("fix-ext")
-                .about("Fix extension according to the file contents.")
-                .arg(path_arg.clone())
+            Command::new("apply")
+                .about("Perform the renames saved by `plan` if the files have not changed.")
                 .arg(
-                    arg!(
-                        -a --append ... "Append instead of replacing."
-                    )
-                    .action(clap::ArgAction::SetTrue),
+                    Arg::new("plan")
+                        .help("The saved plan")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true),
                 ),
         )
         .subcommand(
-            Command::new("remove")
-                .about("Remove part of a name from all files.")
+            Command::new("undo")
+                .about("Revert a batch of renames (the last one by default).")
                 .arg(
-                    Arg::new("pattern")
-                        .help("The string to remove.")
+                    Arg::new("batch")
+                        .help("Id of the batch as shown by `history`")
                         .action(ArgAction::Set)
-                        .value_parser(value_parser!(String))
-                        .required(true),
-                )
-                .arg(path_arg.clone()),
+                        .value_parser(value_parser!(u64)),
+                ),
         )
         .subcommand(
-            Command::new("change-case")
-                .about("Change case of all files.")
-                .arg(path_arg.clone())
+            Command::new("history")
+                .about("List the recorded batches of renames.")
                 .arg(
                     arg!(
-                        -u --upper ... "Upper case (default: false)."
+                        -v --verbose ... "Show the individual renames."
                     )
                     .action(clap::ArgAction::SetTrue),
                 ),
@@ -175,6 +707,53 @@ fn create_cli_command() -> Command {
 fn main() {
     let command = create_cli_command();
     let matches = command.get_matches();
-    let config = parse_config(&matches);
-    run(&config);
+    let result = match matches.subcommand() {
+        Some(("history", history_matches)) => print_history(history_matches),
+        Some(("plan", plan_matches)) => print_plan(plan_matches),
+        _ => parse_config(&matches).and_then(|config| {
+            // The confirmations cannot be read from stdin when it holds the paths
+            let input = match reads_stdin(&matches) {
+                true => Input::Tty,
+                false => Input::Stdin,
+            };
+            let reporter: Box<dyn Reporter> =
+                match matches.get_one::<String>("format").map(String::as_str) {
+                    Some("json") => Box::new(FormattedReporter {
+                        format: OutputFormat::Json,
+                        input,
+                    }),
+                    Some("ndjson") => Box::new(FormattedReporter {
+                        format: OutputFormat::Ndjson,
+                        input,
+                    }),
+                    Some("tsv") => Box::new(FormattedReporter {
+                        format: OutputFormat::Tsv,
+                        input,
+                    }),
+                    _ => Box::new(TerminalReporter {
+                        show_unchanged: matches.get_flag("unchanged"),
+                        granularity: match matches.get_one::<String>("diff").map(String::as_str) {
+                            Some("chars") => Some(Granularity::Chars),
+                            Some("words") => Some(Granularity::Words),
+                            _ => None,
+                        },
+                        columns: matches.get_flag("columns"),
+                        input,
+                    }),
+                };
+            let totals = run(&config, reporter.as_ref())?.totals();
+            // Scripts learn about the failed renames from the exit code
+            if totals.failed + totals.not_restored > 0 {
+                exit(1);
+            }
+            Ok(())
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        if let MurenError::Conflicts(_) = e {
+            eprintln!("Use `--on-conflict` to choose how to resolve the conflicts.");
+        }
+        exit(1);
+    }
 }
@@ -0,0 +1,866 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{path_bytes, Conflict, RenameIntent, Resolution};
+use crate::comparison::NameComparison;
+use crate::links::normalize;
+use crate::target_fs::TargetFs;
+
+/// A single filesystem rename, as executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenameStep {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Is `to` only a temporary name used to break a cycle?
+    pub temporary: bool,
+}
+
+/// What to do when a new name is already taken (on the disk or by another file of the batch).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// Refuse to perform the batch.
+    #[default]
+    Fail,
+    /// Keep the conflicting files under their old names.
+    Skip,
+    /// Replace the existing files (not applicable to duplicates within the batch).
+    Overwrite,
+    /// Make the name unique using a suffix pattern, in which `{n}` stands for a number.
+    AutoSuffix(String),
+}
+
+/// What to do with the directories left empty by the renames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum EmptyDirs {
+    #[default]
+    Keep,
+    Remove,
+    /// Remove only these (e.g. the directories created by an undone batch).
+    RemoveOnly(Vec<PathBuf>),
+}
+
+/// A directory created for the new names, or removed after the files moved out of it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirChange {
+    Created(#[serde(with = "path_bytes")] PathBuf),
+    Removed(#[serde(with = "path_bytes")] PathBuf),
+}
+
+/// Reasons why the intents cannot be turned into a safe sequence of renames.
+#[derive(Debug, PartialEq)]
+pub enum PlanError {
+    /// More than one file would be renamed to the same name.
+    DuplicateTarget(PathBuf),
+    /// A file would be renamed to a name still occupied by another source.
+    TargetNotVacated(PathBuf),
+}
+
+impl Display for PlanError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            PlanError::DuplicateTarget(path) => {
+                write!(f, "Multiple files would be renamed to {}", path.display())
+            }
+            PlanError::TargetNotVacated(path) => {
+                write!(
+                    f,
+                    "Target {} is still occupied by a file that is not renamed",
+                    path.display()
+                )
+            }
+        }
+    }
+}
+
+/// Order the renames so that no file is overwritten by another rename of the batch.
+///
+/// The contents of directories are renamed before the directories themselves.
+/// Chains (`1 → 2`, `2 → 3`) are renamed from their end, cycles (`a → b`, `b → a`)
+/// are broken by moving one of the files to a temporary name first. Names are compared
+/// as the filesystem does, so that case-only renames on case-insensitive filesystems
+/// are done in two steps too.
+pub fn plan_renames(
+    intents: &[RenameIntent],
+    comparison: NameComparison,
+) -> Result<Vec<RenameStep>, PlanError> {
+    let changed: Vec<&RenameIntent> = intents.iter().filter(|i| i.is_changed()).collect();
+
+    let mut targets = HashSet::new();
+    for intent in &changed {
+        if !targets.insert(comparison.key(&intent.new_name)) {
+            return Err(PlanError::DuplicateTarget(intent.new_name.clone()));
+        }
+    }
+
+    // `blocked_by[i] == Some(j)` means that `j` has to move away before `i` can be renamed.
+    let sources: HashMap<PathBuf, usize> = changed
+        .iter()
+        .enumerate()
+        .map(|(index, intent)| (comparison.key(&intent.old_name), index))
+        .collect();
+    let blocked_by: Vec<Option<usize>> = changed
+        .iter()
+        .map(|intent| sources.get(&comparison.key(&intent.new_name)).copied())
+        .collect();
+
+    let mut reserved: HashSet<PathBuf> = intents
+        .iter()
+        .flat_map(|intent| [intent.old_name.clone(), intent.new_name.clone()])
+        .collect();
+    let mut steps = Vec::with_capacity(changed.len());
+    let mut done = vec![false; changed.len()];
+
+    // From the deepest path outward, while the parent directories still have their old names
+    let mut order: Vec<usize> = (0..changed.len()).collect();
+    order.sort_by_key(|&index| Reverse(changed[index].old_name.components().count()));
+
+    for start in order {
+        if done[start] {
+            continue;
+        }
+
+        // Walk to the end of the chain, or until we come back to `start`.
+        let mut chain = vec![start];
+        let mut is_cycle = false;
+        while let Some(next) = blocked_by[*chain.last().unwrap()] {
+            if next == start {
+                is_cycle = true;
+                break;
+            }
+            if done[next] {
+                break;
+            }
+            chain.push(next);
+        }
+
+        if is_cycle {
+            let first = changed[start];
+            let temporary_name = find_temporary_name(&first.old_name, &reserved);
+            reserved.insert(temporary_name.clone());
+            steps.push(RenameStep {
+                from: first.old_name.clone(),
+                to: temporary_name.clone(),
+                temporary: true,
+            });
+            for &index in chain.iter().skip(1).rev() {
+                steps.push(step_for(changed[index]));
+                done[index] = true;
+            }
+            steps.push(RenameStep {
+                from: temporary_name,
+                to: first.new_name.clone(),
+                temporary: false,
+            });
+            done[start] = true;
+        } else {
+            // The chain may continue into already planned renames; those are in `steps` already.
+            for &index in chain.iter().rev() {
+                steps.push(step_for(changed[index]));
+                done[index] = true;
+            }
+        }
+    }
+
+    check_order(intents, &steps, comparison)?;
+    Ok(steps)
+}
+
+/// Express the new names of files in renamed directories by the old directory names.
+///
+/// The directories are renamed after their contents, so with `d → D`,
+/// a file moving from `d/f` to `D/g` is renamed to `d/g` first.
+pub fn nest_in_old_dirs(intents: &mut [RenameIntent]) {
+    let renamed: HashMap<PathBuf, PathBuf> = intents
+        .iter()
+        .filter(|intent| intent.is_changed())
+        .map(|intent| (intent.old_name.clone(), intent.new_name.clone()))
+        .collect();
+    for intent in intents.iter_mut() {
+        // The deepest renamed directory that the file stays in
+        let dir = intent.old_name.ancestors().skip(1).find_map(|old| {
+            let new = renamed.get(old)?;
+            is_inside(&intent.new_name, new).then_some((old, new))
+        });
+        if let Some((old, new)) = dir {
+            intent.new_name = old.join(intent.new_name.strip_prefix(new).unwrap());
+        }
+    }
+}
+
+/// Where paths are after the directories containing them were renamed.
+pub struct Relocation {
+    /// New names by old names of the renamed directories (and files).
+    renamed: HashMap<PathBuf, PathBuf>,
+}
+
+impl Relocation {
+    pub fn new(intents: &[RenameIntent]) -> Self {
+        let mut renamed = HashMap::new();
+        for intent in intents {
+            if intent.is_changed() && !intent.new_name.starts_with(&intent.old_name) {
+                renamed
+                    .entry(intent.old_name.clone())
+                    .or_insert_with(|| intent.new_name.clone());
+            }
+        }
+        Relocation { renamed }
+    }
+
+    /// Where the path is after the directories containing it were renamed.
+    pub fn apply(&self, path: &Path) -> PathBuf {
+        self.apply_within(path, self.renamed.len())
+    }
+
+    /// `apply` giving up after `limit` directories (which only directories moved into each other need).
+    fn apply_within(&self, path: &Path, limit: usize) -> PathBuf {
+        if limit == 0 {
+            return path.to_path_buf();
+        }
+        for dir in path.ancestors().skip(1) {
+            if let Some(new_dir) = self.renamed.get(dir) {
+                let relocated_dir = self.apply_within(new_dir, limit - 1);
+                return relocated_dir.join(path.strip_prefix(dir).unwrap());
+            }
+        }
+        path.to_path_buf()
+    }
+}
+
+/// The missing parent directories of the new names, parents first.
+///
+/// Directories that the batch renames something to are not created.
+pub fn missing_dirs(intents: &[RenameIntent]) -> Vec<PathBuf> {
+    let new_names: Vec<PathBuf> = moving(intents).map(|(_, new_name)| new_name).collect();
+    let mut dirs: Vec<PathBuf> = new_names
+        .iter()
+        .flat_map(|new_name| {
+            new_name
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        })
+        .filter(|dir| !new_names.iter().any(|new_name| dir.starts_with(new_name)))
+        .map(Path::to_path_buf)
+        .collect();
+    dirs.sort_by_key(|dir| (dir.components().count(), dir.clone()));
+    dirs.dedup();
+    dirs
+}
+
+/// The directories that only contain files moved away by the renames, deepest first.
+pub fn emptied_dirs(intents: &[RenameIntent]) -> Vec<PathBuf> {
+    let moving: Vec<(PathBuf, PathBuf)> = moving(intents).collect();
+    let mut gone: HashSet<PathBuf> = moving
+        .iter()
+        .map(|(old_name, _)| old_name.clone())
+        .collect();
+    let mut candidates: BTreeSet<(Reverse<usize>, PathBuf)> = BTreeSet::new();
+    let add_parent = |path: &Path, candidates: &mut BTreeSet<_>| {
+        // Never the working directory (`""` or `.`) or the root
+        if let Some(dir) = path.parent().filter(|dir| dir.file_name().is_some()) {
+            candidates.insert((Reverse(dir.components().count()), dir.to_path_buf()));
+        }
+    };
+    for (old_name, _) in &moving {
+        add_parent(old_name, &mut candidates);
+    }
+
+    let mut emptied = Vec::new();
+    while let Some((_, dir)) = candidates.pop_first() {
+        let receives = moving
+            .iter()
+            .any(|(_, new_name)| new_name.starts_with(&dir));
+        let is_emptied = !receives
+            && fs::read_dir(&dir).is_ok_and(|entries| {
+                entries
+                    .into_iter()
+                    .all(|entry| entry.is_ok_and(|entry| gone.contains(&entry.path())))
+            });
+        if is_emptied {
+            add_parent(&dir, &mut candidates);
+            gone.insert(dir.clone());
+            emptied.push(dir);
+        }
+    }
+    emptied
+}
+
+/// The old and new names of the files that are renamed, normalized to be compared as paths.
+fn moving(intents: &[RenameIntent]) -> impl Iterator<Item = (PathBuf, PathBuf)> + '_ {
+    intents
+        .iter()
+        .filter(|intent| intent.is_changed() && !intent.is_blocked())
+        .map(|intent| (normalize(&intent.old_name), normalize(&intent.new_name)))
+}
+
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    path != dir && path.starts_with(dir)
+}
+
+/// Apply the `..` (and drop the `.`) components of the new names.
+///
+/// The directories before a `..` may not exist yet, so the new name would not resolve.
+pub fn normalize_new_names(intents: &mut [RenameIntent]) {
+    for intent in intents.iter_mut().filter(|intent| intent.is_changed()) {
+        intent.new_name = normalize(&intent.new_name);
+    }
+}
+
+/// Keep the files whose new name would lose the bytes of the old one that are not valid UTF-8.
+pub fn skip_lossy_names(intents: &mut [RenameIntent]) {
+    for intent in intents.iter_mut().filter(|intent| intent.is_lossy()) {
+        let proposed = std::mem::replace(&mut intent.new_name, intent.old_name.clone());
+        intent.conflict = Some(Conflict::LossyName);
+        intent.resolution = Some(Resolution::Skipped { proposed });
+    }
+}
+
+/// Mark the new names that are not valid on the target filesystem.
+///
+/// Only the changed part of the path is checked; the files keep their names
+/// with `ConflictPolicy::Skip`, otherwise the conflicts block the batch.
+pub fn check_names(intents: &mut [RenameIntent], target: TargetFs, policy: &ConflictPolicy) {
+    for intent in intents.iter_mut().filter(|intent| intent.is_changed()) {
+        let common = intent
+            .new_name
+            .components()
+            .zip(intent.old_name.components())
+            .take_while(|(new, old)| new == old)
+            .count();
+        let changed: PathBuf = intent.new_name.components().skip(common).collect();
+        let Some(problem) = target.check_path(&changed) else {
+            continue;
+        };
+        intent.conflict = Some(Conflict::InvalidName { target, problem });
+        if *policy == ConflictPolicy::Skip {
+            let proposed = std::mem::replace(&mut intent.new_name, intent.old_name.clone());
+            intent.resolution = Some(Resolution::Skipped { proposed });
+        }
+    }
+}
+
+/// Find the intents whose new name is already taken and deal with them according to the policy.
+///
+/// A name is taken when it exists on the disk and its file does not move away,
+/// or when an earlier file of the batch is renamed to it. Conflicts that the policy
+/// cannot resolve are only marked.
+pub fn resolve_conflicts(
+    intents: &mut [RenameIntent],
+    policy: &ConflictPolicy,
+    comparison: NameComparison,
+) {
+    // Skipping a file keeps its name occupied, which may cause new conflicts.
+    while mark_conflicts(intents, policy, comparison) {}
+}
+
+/// One pass of conflict resolution, returning whether any file was skipped.
+fn mark_conflicts(
+    intents: &mut [RenameIntent],
+    policy: &ConflictPolicy,
+    comparison: NameComparison,
+) -> bool {
+    let moved: HashSet<PathBuf> = intents
+        .iter()
+        .filter(|intent| intent.is_changed())
+        .map(|intent| comparison.key(&intent.old_name))
+        .collect();
+    let mut claimed: HashSet<PathBuf> = intents
+        .iter()
+        .filter(|intent| !intent.is_changed())
+        .map(|intent| comparison.key(&intent.old_name))
+        .collect();
+    let mut skipped_any = false;
+
+    for intent in intents.iter_mut().filter(|intent| intent.is_changed()) {
+        if intent.is_blocked() {
+            continue;
+        }
+        let is_taken = |name: &Path, claimed: &HashSet<PathBuf>| {
+            let key = comparison.key(name);
+            claimed.contains(&key) || (!moved.contains(&key) && name.symlink_metadata().is_ok())
+        };
+        let conflict = if claimed.contains(&comparison.key(&intent.new_name)) {
+            Conflict::Duplicate
+        } else if is_taken(&intent.new_name, &claimed) {
+            Conflict::ExistingFile
+        } else {
+            claimed.insert(comparison.key(&intent.new_name));
+            continue;
+        };
+
+        intent.conflict = Some(conflict);
+        intent.resolution = match (policy, conflict) {
+            (ConflictPolicy::Skip, _) => {
+                skipped_any = true;
+                let proposed = std::mem::replace(&mut intent.new_name, intent.old_name.clone());
+                Some(Resolution::Skipped { proposed })
+            }
+            (ConflictPolicy::Overwrite, Conflict::ExistingFile) => Some(Resolution::Overwrite),
+            (ConflictPolicy::AutoSuffix(pattern), _) => {
+                intent.new_name = (2..)
+                    .map(|n| with_suffix(&intent.new_name, pattern, n))
+                    .find(|candidate| {
+                        !is_taken(candidate, &claimed)
+                            && !moved.contains(&comparison.key(candidate))
+                    })
+                    .unwrap();
+                Some(Resolution::Suffixed)
+            }
+            _ => None,
+        };
+        claimed.insert(comparison.key(&intent.new_name));
+    }
+    skipped_any
+}
+
+/// Insert the numbered suffix between the stem and the extension.
+fn with_suffix(path: &Path, pattern: &str, n: usize) -> PathBuf {
+    let suffix = if pattern.contains("{n}") {
+        pattern.replace("{n}", &n.to_string())
+    } else {
+        format!("{pattern}{n}")
+    };
+    let mut name = OsString::from(path.file_stem().unwrap_or_default());
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+fn step_for(intent: &RenameIntent) -> RenameStep {
+    RenameStep {
+        from: intent.old_name.clone(),
+        to: intent.new_name.clone(),
+        temporary: false,
+    }
+}
+
+/// Verify that no step renames a file to a name whose original file has not moved yet.
+fn check_order(
+    intents: &[RenameIntent],
+    steps: &[RenameStep],
+    comparison: NameComparison,
+) -> Result<(), PlanError> {
+    let mut occupied: HashSet<PathBuf> = intents
+        .iter()
+        .map(|intent| comparison.key(&intent.old_name))
+        .collect();
+    for step in steps {
+        if occupied.contains(&comparison.key(&step.to)) {
+            return Err(PlanError::TargetNotVacated(step.to.clone()));
+        }
+        occupied.remove(&comparison.key(&step.from));
+    }
+    Ok(())
+}
+
+/// A name next to `path` that is neither on the disk nor used by the batch.
+fn find_temporary_name(path: &Path, reserved: &HashSet<PathBuf>) -> PathBuf {
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    (0..)
+        .map(|counter| {
+            let mut name = OsString::from(".");
+            name.push(file_name);
+            name.push(format!(".muren-{counter}"));
+            path.with_file_name(name)
+        })
+        .find(|candidate| !reserved.contains(candidate) && !candidate.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target_fs::NameProblem;
+
+    fn intents(pairs: &[(&str, &str)]) -> Vec<RenameIntent> {
+        pairs
+            .iter()
+            .map(|&(old, new)| RenameIntent::new(PathBuf::from(old), PathBuf::from(new)))
+            .collect()
+    }
+
+    fn renames(steps: &[RenameStep]) -> Vec<(String, String)> {
+        steps
+            .iter()
+            .map(|step| {
+                (
+                    step.from.to_string_lossy().to_string(),
+                    step.to.to_string_lossy().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|&(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    fn plan_renames_exact(intents: &[RenameIntent]) -> Result<Vec<RenameStep>, PlanError> {
+        plan_renames(intents, NameComparison::Exact)
+    }
+
+    #[test]
+    fn test_independent() {
+        let steps = plan_renames_exact(&intents(&[("a", "x"), ("b", "b"), ("c", "y")])).unwrap();
+        assert_eq!(renames(&steps), pairs(&[("a", "x"), ("c", "y")]));
+    }
+
+    #[test]
+    fn test_chain() {
+        let steps = plan_renames_exact(&intents(&[("1", "2"), ("2", "3"), ("3", "4")])).unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[("3", "4"), ("2", "3"), ("1", "2")])
+        );
+    }
+
+    #[test]
+    fn test_chain_started_in_the_middle() {
+        let steps = plan_renames_exact(&intents(&[("2", "3"), ("1", "2"), ("3", "4")])).unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[("3", "4"), ("2", "3"), ("1", "2")])
+        );
+    }
+
+    #[test]
+    fn test_swap() {
+        let steps = plan_renames_exact(&intents(&[("a", "b"), ("b", "a")])).unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[("a", ".a.muren-0"), ("b", "a"), (".a.muren-0", "b")])
+        );
+        assert!(steps[0].temporary);
+        assert!(!steps[2].temporary);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let steps = plan_renames_exact(&intents(&[("a", "b"), ("b", "c"), ("c", "a")])).unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[
+                ("a", ".a.muren-0"),
+                ("c", "a"),
+                ("b", "c"),
+                (".a.muren-0", "b")
+            ])
+        );
+    }
+
+    /// Files `a`, `b`, `c` and `taken`, with intents `a → b`, `b → free`, `c → taken`, `x → free`.
+    fn resolve_in_tempdir(policy: ConflictPolicy) -> Vec<RenameIntent> {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a", "b", "c", "taken"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let mut intents = intents(&[
+            (&path("a"), &path("b")),
+            (&path("b"), &path("free")),
+            (&path("c"), &path("taken")),
+            (&path("x"), &path("free")),
+        ]);
+        resolve_conflicts(&mut intents, &policy, NameComparison::Exact);
+        for intent in intents.iter_mut() {
+            intent.old_name = intent
+                .old_name
+                .strip_prefix(dir.path())
+                .unwrap()
+                .to_path_buf();
+            intent.new_name = intent
+                .new_name
+                .strip_prefix(dir.path())
+                .unwrap()
+                .to_path_buf();
+        }
+        intents
+    }
+
+    fn outcomes(intents: &[RenameIntent]) -> Vec<(String, Option<Conflict>, Option<Resolution>)> {
+        intents
+            .iter()
+            .map(|i| {
+                let new_name = i.new_name.to_string_lossy().to_string();
+                (new_name, i.conflict, i.resolution.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_conflicts_fail() {
+        let intents = resolve_in_tempdir(ConflictPolicy::Fail);
+        assert_eq!(
+            outcomes(&intents),
+            [
+                (String::from("b"), None, None),
+                (String::from("free"), None, None),
+                (String::from("taken"), Some(Conflict::ExistingFile), None),
+                (String::from("free"), Some(Conflict::Duplicate), None),
+            ]
+        );
+        assert!(intents[2].is_blocked());
+    }
+
+    #[test]
+    fn test_conflicts_overwrite() {
+        let intents = resolve_in_tempdir(ConflictPolicy::Overwrite);
+        assert_eq!(intents[2].resolution, Some(Resolution::Overwrite));
+        assert!(intents[3].is_blocked());
+    }
+
+    #[test]
+    fn test_conflicts_skip() {
+        let intents = resolve_in_tempdir(ConflictPolicy::Skip);
+        assert!(!intents[2].is_changed());
+        assert!(matches!(
+            &intents[2].resolution,
+            Some(Resolution::Skipped { proposed }) if proposed.ends_with("taken")
+        ));
+        assert!(!intents[3].is_changed());
+        assert!(intents.iter().all(|i| !i.is_blocked()));
+    }
+
+    #[test]
+    fn test_conflicts_skip_cascades() {
+        let mut batch = intents(&[("a", "b"), ("b", "c"), ("d", "c")]);
+        resolve_conflicts(&mut batch, &ConflictPolicy::Skip, NameComparison::Exact);
+        let changed: Vec<bool> = batch.iter().map(|i| i.is_changed()).collect();
+        assert_eq!(changed, [true, true, false]);
+
+        // `b` stays, so `a → b` cannot happen either.
+        let mut batch = intents(&[("d", "c"), ("b", "c"), ("a", "b")]);
+        resolve_conflicts(&mut batch, &ConflictPolicy::Skip, NameComparison::Exact);
+        let changed: Vec<bool> = batch.iter().map(|i| i.is_changed()).collect();
+        assert_eq!(changed, [true, false, false]);
+    }
+
+    #[test]
+    fn test_conflicts_auto_suffix() {
+        let intents = resolve_in_tempdir(ConflictPolicy::AutoSuffix(String::from(" ({n})")));
+        assert_eq!(intents[2].new_name, PathBuf::from("taken (2)"));
+        assert_eq!(intents[3].new_name, PathBuf::from("free (2)"));
+        assert!(intents.iter().all(|i| !i.is_blocked()));
+    }
+
+    #[test]
+    fn test_check_names() {
+        let mut checked = intents(&[("a", "a?"), ("dir/b", "dir/b.txt"), ("c", "new:dir/c")]);
+        check_names(&mut checked, TargetFs::Windows, &ConflictPolicy::Fail);
+        let problems: Vec<Option<Conflict>> = checked.iter().map(|i| i.conflict).collect();
+        let invalid = |c| {
+            Some(Conflict::InvalidName {
+                target: TargetFs::Windows,
+                problem: NameProblem::Character(c),
+            })
+        };
+        assert_eq!(problems, [invalid('?'), None, invalid(':')]);
+        assert!(checked[0].is_blocked());
+
+        let mut skipped = intents(&[("a", "a?")]);
+        check_names(&mut skipped, TargetFs::Windows, &ConflictPolicy::Skip);
+        assert!(!skipped[0].is_changed() && !skipped[0].is_blocked());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_skip_lossy_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let old = PathBuf::from(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+        let mut intents = [
+            RenameIntent::new(
+                old.clone(),
+                PathBuf::from(old.to_string_lossy().to_uppercase()),
+            ),
+            RenameIntent::new(PathBuf::from("a"), PathBuf::from("b")),
+        ];
+        skip_lossy_names(&mut intents);
+        assert_eq!(intents[0].new_name, old);
+        assert_eq!(intents[0].conflict, Some(Conflict::LossyName));
+        assert!(!intents[0].is_blocked());
+        assert!(intents[1].is_changed());
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(
+            with_suffix(Path::new("dir/photo.jpg"), "_{n}", 3),
+            PathBuf::from("dir/photo_3.jpg")
+        );
+        assert_eq!(
+            with_suffix(Path::new(".gitignore"), " ({n})", 2),
+            PathBuf::from(".gitignore (2)")
+        );
+    }
+
+    #[test]
+    fn test_case_only_rename() {
+        let batch = intents(&[("Foo.txt", "foo.txt")]);
+        let steps = plan_renames(&batch, NameComparison::CaseInsensitive).unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[
+                ("Foo.txt", ".Foo.txt.muren-0"),
+                (".Foo.txt.muren-0", "foo.txt")
+            ])
+        );
+        assert_eq!(plan_renames_exact(&batch).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_case_insensitive_duplicates() {
+        let mut batch = intents(&[("a", "X.txt"), ("b", "x.txt"), ("c", "c")]);
+        resolve_conflicts(
+            &mut batch,
+            &ConflictPolicy::Fail,
+            NameComparison::CaseInsensitive,
+        );
+        assert_eq!(batch[1].conflict, Some(Conflict::Duplicate));
+        assert_eq!(
+            plan_renames(&batch, NameComparison::CaseInsensitive),
+            Err(PlanError::DuplicateTarget(PathBuf::from("x.txt")))
+        );
+
+        let mut batch = intents(&[("a", "hn\u{11b}d\u{fd}"), ("b", "hne\u{30c}dy\u{301}")]);
+        resolve_conflicts(
+            &mut batch,
+            &ConflictPolicy::Fail,
+            NameComparison::Normalized,
+        );
+        assert_eq!(batch[1].conflict, Some(Conflict::Duplicate));
+    }
+
+    #[test]
+    fn test_duplicate_target() {
+        assert_eq!(
+            plan_renames_exact(&intents(&[("a", "c"), ("b", "c")])),
+            Err(PlanError::DuplicateTarget(PathBuf::from("c")))
+        );
+    }
+
+    #[test]
+    fn test_target_not_vacated() {
+        assert_eq!(
+            plan_renames_exact(&intents(&[("a", "b"), ("b", "b")])),
+            Err(PlanError::TargetNotVacated(PathBuf::from("b")))
+        );
+    }
+
+    #[test]
+    fn test_contents_before_directories() {
+        let steps = plan_renames_exact(&intents(&[
+            ("a", "A"),
+            ("a/b", "a/B"),
+            ("a/b/c", "a/b/C"),
+            ("d", "D"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            renames(&steps),
+            pairs(&[("a/b/c", "a/b/C"), ("a/b", "a/B"), ("a", "A"), ("d", "D")])
+        );
+    }
+
+    #[test]
+    fn test_nest_in_old_dirs() {
+        let mut nested = intents(&[
+            ("a", "A"),
+            ("a/b", "A/B"),
+            ("a/b/c", "A/B/C"),
+            ("a/d", "x/d"),
+        ]);
+        nest_in_old_dirs(&mut nested);
+        let new_names: Vec<&Path> = nested.iter().map(|i| i.new_name.as_path()).collect();
+        assert_eq!(new_names, ["A", "a/B", "a/b/C", "x/d"].map(Path::new));
+
+        let relocation = Relocation::new(&nested);
+        assert_eq!(relocation.apply(Path::new("a/b/c")), Path::new("A/B/c"));
+        assert_eq!(relocation.apply(Path::new("a/b/C")), Path::new("A/B/C"));
+        assert_eq!(relocation.apply(Path::new("e/f")), Path::new("e/f"));
+    }
+
+    #[test]
+    fn test_nest_large_batch() {
+        // Quadratic lookups would take many seconds here
+        let mut batch: Vec<RenameIntent> = (0..50_000)
+            .map(|n| {
+                RenameIntent::new(
+                    PathBuf::from(format!("dir/{n}")),
+                    PathBuf::from(format!("dir/x_{n}")),
+                )
+            })
+            .collect();
+        batch.push(RenameIntent::new(
+            PathBuf::from("dir"),
+            PathBuf::from("DIR"),
+        ));
+        let start = std::time::Instant::now();
+        nest_in_old_dirs(&mut batch);
+        let relocation = Relocation::new(&batch);
+        let relocated: Vec<PathBuf> = batch
+            .iter()
+            .map(|i| relocation.apply(&i.new_name))
+            .collect();
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(batch[7].new_name, Path::new("dir/x_7"));
+        assert_eq!(relocated[7], Path::new("DIR/x_7"));
+    }
+
+    #[test]
+    fn test_normalize_new_names() {
+        let mut normalized = intents(&[("a", "2023/05/../a"), ("b", "./b"), ("c", "x/./c")]);
+        normalize_new_names(&mut normalized);
+        let new_names: Vec<&Path> = normalized.iter().map(|i| i.new_name.as_path()).collect();
+        assert_eq!(
+            new_names,
+            [Path::new("2023/a"), Path::new("b"), Path::new("x/c")]
+        );
+        assert!(!normalized[1].is_changed());
+    }
+
+    #[test]
+    fn test_directory_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        std::fs::create_dir_all(path("old/sub")).unwrap();
+        std::fs::create_dir_all(path("kept")).unwrap();
+        for name in ["old/a", "old/sub/b", "kept/c", "kept/d"] {
+            std::fs::write(path(name), "").unwrap();
+        }
+        let planned = intents(&[
+            (&path("old/a"), &path("2023/05/a")),
+            (&path("old/sub/b"), &path("2023/b")),
+            (&path("kept/c"), &path("2024/c")),
+        ]);
+
+        assert_eq!(
+            missing_dirs(&planned),
+            [path("2023"), path("2024"), path("2023/05")].map(PathBuf::from)
+        );
+        assert_eq!(
+            emptied_dirs(&planned),
+            [path("old/sub"), path("old")].map(PathBuf::from)
+        );
+
+        // Only `2023` is missing, and `old` still receives a file
+        let dotted = intents(&[
+            (&path("old/a"), &path("2023/05/../a")),
+            (&path("old/sub/b"), &path("old/sub/../b")),
+        ]);
+        assert_eq!(missing_dirs(&dotted), [PathBuf::from(path("2023"))]);
+        assert_eq!(emptied_dirs(&dotted), [PathBuf::from(path("old/sub"))]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::commands::{path_bytes, RenameIntent, Resolution};
+use crate::error::MurenError;
+use crate::execute::Transfer;
+use crate::filter::Exclusion;
+use crate::links::Relink;
+use crate::plan::{DirChange, RenameStep};
+
+/// What happened to a single file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenameStatus {
+    /// Would be renamed (dry run).
+    Planned,
+    Renamed,
+    /// The new name is the same as the old one.
+    Unchanged,
+    /// Not renamed because of a conflict (or an earlier failure in the atomic mode).
+    Skipped,
+    /// Renamed, but reverted because another rename of the batch failed.
+    RolledBack,
+    Failed(String),
+    /// Moved, but could not be brought back to its original name after a failure.
+    NotRestored {
+        left_at: PathBuf,
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct RenameOutcome {
+    pub intent: RenameIntent,
+    pub status: RenameStatus,
+}
+
+/// Number of files in each status.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    pub planned: usize,
+    pub renamed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub rolled_back: usize,
+    pub failed: usize,
+    pub not_restored: usize,
+}
+
+/// The result of a batch, one outcome per suggested rename.
+#[derive(Clone, Debug, Default)]
+pub struct RenameReport {
+    pub outcomes: Vec<RenameOutcome>,
+    /// Were the renames only planned, not performed?
+    pub dry: bool,
+}
+
+impl RenameReport {
+    pub fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for outcome in &self.outcomes {
+            match outcome.status {
+                RenameStatus::Planned => totals.planned += 1,
+                RenameStatus::Renamed => totals.renamed += 1,
+                RenameStatus::Unchanged => totals.unchanged += 1,
+                RenameStatus::Skipped => totals.skipped += 1,
+                RenameStatus::RolledBack => totals.rolled_back += 1,
+                RenameStatus::Failed(_) => totals.failed += 1,
+                RenameStatus::NotRestored { .. } => totals.not_restored += 1,
+            }
+        }
+        totals
+    }
+
+    /// The renames that were actually performed.
+    pub fn renamed(&self) -> Vec<RenameIntent> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.status == RenameStatus::Renamed)
+            .map(|outcome| outcome.intent.clone())
+            .collect()
+    }
+}
+
+impl RenameStatus {
+    /// Stable name of the status in machine-readable output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenameStatus::Planned => "planned",
+            RenameStatus::Renamed => "renamed",
+            RenameStatus::Unchanged => "unchanged",
+            RenameStatus::Skipped => "skipped",
+            RenameStatus::RolledBack => "rolled_back",
+            RenameStatus::Failed(_) => "failed",
+            RenameStatus::NotRestored { .. } => "not_restored",
+        }
+    }
+}
+
+/// The status of an intent that has not been (or will not be) executed.
+pub fn status_before_execution(intent: &RenameIntent) -> RenameStatus {
+    if let (Some(conflict), true) = (&intent.conflict, intent.is_blocked()) {
+        RenameStatus::Failed(conflict.to_string())
+    } else if intent.is_changed() {
+        RenameStatus::Planned
+    } else if matches!(intent.resolution, Some(Resolution::Skipped { .. })) {
+        RenameStatus::Skipped
+    } else {
+        RenameStatus::Unchanged
+    }
+}
+
+/// Machine-readable output of the outcomes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// A single JSON array.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// Tab-separated values with a header line.
+    Tsv,
+}
+
+/// One outcome with stable field names, as serialized in the machine-readable formats.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OutcomeRecord<'a> {
+    #[serde(serialize_with = "path_bytes::serialize")]
+    pub old: &'a Path,
+    #[serde(serialize_with = "path_bytes::serialize")]
+    pub new: &'a Path,
+    pub changed: bool,
+    pub status: &'static str,
+    pub error: Option<&'a str>,
+    /// Where a file that could not be restored is now.
+    #[serde(serialize_with = "path_bytes::serialize_option")]
+    pub left_at: Option<&'a Path>,
+}
+
+impl<'a> From<&'a RenameOutcome> for OutcomeRecord<'a> {
+    fn from(outcome: &'a RenameOutcome) -> Self {
+        OutcomeRecord {
+            old: &outcome.intent.old_name,
+            new: &outcome.intent.new_name,
+            changed: outcome.intent.is_changed(),
+            status: outcome.status.name(),
+            error: match &outcome.status {
+                RenameStatus::Failed(error) | RenameStatus::NotRestored { error, .. } => {
+                    Some(error)
+                }
+                _ => None,
+            },
+            left_at: match &outcome.status {
+                RenameStatus::NotRestored { left_at, .. } => Some(left_at),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn write(&self, outcomes: &[RenameOutcome], writer: &mut dyn Write) -> io::Result<()> {
+        let records: Vec<OutcomeRecord> = outcomes.iter().map(OutcomeRecord::from).collect();
+        match self {
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, &records)?;
+                writeln!(writer)
+            }
+            OutputFormat::Ndjson => records.iter().try_for_each(|record| {
+                serde_json::to_writer(&mut *writer, record)?;
+                writeln!(writer)
+            }),
+            OutputFormat::Tsv => {
+                writeln!(writer, "old\tnew\tchanged\tstatus\terror\tleft_at")?;
+                records.iter().try_for_each(|record| {
+                    writeln!(
+                        writer,
+                        "{}\t{}\t{}\t{}\t{}\t{}",
+                        escape_tsv(&record.old.to_string_lossy()),
+                        escape_tsv(&record.new.to_string_lossy()),
+                        record.changed,
+                        record.status,
+                        escape_tsv(record.error.unwrap_or_default()),
+                        escape_tsv(&record.left_at.unwrap_or(Path::new("")).to_string_lossy())
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Escape the characters that would break the TSV structure.
+fn escape_tsv(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// The answer to a single rename in the interactive mode.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Review {
+    Accept,
+    Decline,
+    /// Accept with a different new name.
+    Rename(PathBuf),
+    /// Accept this and all the remaining renames.
+    AcceptAll,
+    /// Decline this and all the remaining renames.
+    Quit,
+}
+
+/// Receives the progress of a batch, e.g. to show it in the terminal.
+///
+/// All methods have empty defaults, so that the library stays quiet unless asked otherwise.
+pub trait Reporter {
+    /// Some of the given files were filtered out.
+    fn excluded(&self, _files: &[(PathBuf, Exclusion)]) {}
+
+    /// The suggested renames, shown in dry runs and when conflicts stop the batch.
+    fn preview(&self, _intents: &[RenameIntent]) {}
+
+    /// The directories to create or remove along with the renames.
+    fn planned_dirs(&self, _changes: &[DirChange]) {}
+
+    /// The links to point to the new names of their targets.
+    fn planned_relinks(&self, _relinks: &[Relink]) {}
+
+    /// Ask whether to perform the renames; without a reporter that can ask, nothing is renamed.
+    fn confirm(&self, _intents: &[RenameIntent]) -> Result<bool, MurenError> {
+        Ok(false)
+    }
+
+    /// Ask about a single rename (in the interactive mode); nothing is accepted by default.
+    fn review(&self, _intent: &RenameIntent) -> Result<Review, MurenError> {
+        Ok(Review::Decline)
+    }
+
+    /// A single rename has been attempted.
+    fn renamed(&self, _step: &RenameStep, _result: &Result<Transfer, String>) {}
+
+    /// A directory has been created or removed (or not).
+    fn dir_changed(&self, _change: &DirChange, _result: &Result<(), String>) {}
+
+    /// A link has been pointed to its new target (or not).
+    fn relinked(&self, _relink: &Relink, _result: &Result<(), String>) {}
+
+    /// A rename failed in the atomic mode and `count` performed renames are about to be reverted.
+    fn rolling_back(&self, _count: usize) {}
+
+    /// A performed rename has been reverted (or not).
+    fn rolled_back(&self, _step: &RenameStep, _result: &Result<Transfer, String>) {}
+
+    /// Something went wrong without affecting the renames.
+    fn warn(&self, _message: &str) {}
+
+    /// The batch is over.
+    fn finished(&self, _report: &RenameReport) {}
+}
+
+/// A reporter that does not report anything.
+pub struct Silent;
+
+impl Reporter for Silent {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn outcomes() -> Vec<RenameOutcome> {
+        let outcome = |old: &str, new: &str, status| RenameOutcome {
+            intent: RenameIntent::new(PathBuf::from(old), PathBuf::from(new)),
+            status,
+        };
+        vec![
+            outcome("a", "b", RenameStatus::Renamed),
+            outcome("c", "c", RenameStatus::Unchanged),
+            outcome("d\te", "f", RenameStatus::Failed(String::from("denied"))),
+            outcome(
+                "g",
+                "h",
+                RenameStatus::NotRestored {
+                    left_at: PathBuf::from("h"),
+                    error: String::from("denied"),
+                },
+            ),
+        ]
+    }
+
+    fn written(format: OutputFormat) -> String {
+        let mut output = Vec::new();
+        format.write(&outcomes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_json() {
+        let value: serde_json::Value = serde_json::from_str(&written(OutputFormat::Json)).unwrap();
+        assert_eq!(
+            value[0],
+            serde_json::json!({"old": "a", "new": "b", "changed": true, "status": "renamed", "error": null, "left_at": null})
+        );
+        assert_eq!(value[2]["error"], "denied");
+        assert_eq!(value[3]["status"], "not_restored");
+        assert_eq!(value[3]["left_at"], "h");
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let output = written(OutputFormat::Ndjson);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            r#"{"old":"c","new":"c","changed":false,"status":"unchanged","error":null,"left_at":null}"#
+        );
+    }
+
+    #[test]
+    fn test_tsv() {
+        assert_eq!(
+            written(OutputFormat::Tsv),
+            "old\tnew\tchanged\tstatus\terror\tleft_at\n\
+             a\tb\ttrue\trenamed\t\t\n\
+             c\tc\tfalse\tunchanged\t\t\n\
+             d\\te\tf\ttrue\tfailed\tdenied\t\n\
+             g\th\ttrue\tnot_restored\tdenied\th\n"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{path_bytes, RenameCommand, RenameIntent};
+use crate::error::MurenError;
+
+/// What a file looked like when the plan was made.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileState {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inode: Option<u64>,
+}
+
+impl FileState {
+    /// The current state of the file (the link itself for symlinks).
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = path.symlink_metadata()?;
+        Ok(FileState {
+            size: metadata.len(),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_nanos() as u64),
+            inode: inode(&metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannedRename {
+    #[serde(flatten)]
+    pub intent: RenameIntent,
+    /// Missing only in hand-written plans, which cannot be applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<FileState>,
+}
+
+/// Suggested renames saved to be reviewed and applied later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedPlan {
+    #[serde(with = "path_bytes")]
+    pub working_dir: PathBuf,
+    pub renames: Vec<PlannedRename>,
+}
+
+impl SavedPlan {
+    /// Record the intents together with the current state of their files, which must exist.
+    pub fn new(intents: Vec<RenameIntent>) -> Result<Self, MurenError> {
+        let mut missing = Vec::new();
+        let mut renames = Vec::new();
+        for intent in intents {
+            match FileState::of(&intent.old_name) {
+                Ok(state) => renames.push(PlannedRename {
+                    source: Some(state),
+                    intent,
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push(intent.old_name),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(MurenError::MissingSources(missing));
+        }
+        Ok(SavedPlan {
+            working_dir: env::current_dir()?,
+            renames,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MurenError> {
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(MurenError::InvalidPlan)
+    }
+}
+
+/// Perform the renames of a saved plan, provided that none of the files has changed since.
+pub struct Apply {
+    pub plan: SavedPlan,
+}
+
+impl Apply {
+    /// Paths are saved relative to the working directory of the plan.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match env::current_dir() {
+            Ok(dir) if dir == self.plan.working_dir => path.to_path_buf(),
+            _ => self.plan.working_dir.join(path),
+        }
+    }
+}
+
+impl RenameCommand for Apply {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        Ok(self
+            .plan
+            .renames
+            .iter()
+            .find(|planned| self.resolve(&planned.intent.old_name) == old_name)
+            .map(|planned| self.resolve(&planned.intent.new_name))
+            .unwrap_or_else(|| old_name.to_path_buf()))
+    }
+
+    /// The files are given by the plan, not by the user.
+    fn suggest_renames(&self, _files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
+        let mut missing = Vec::new();
+        let mut unverified = Vec::new();
+        let mut changed = Vec::new();
+        let mut intents = Vec::new();
+        for planned in &self.plan.renames {
+            let old_name = self.resolve(&planned.intent.old_name);
+            match (FileState::of(&old_name).ok(), &planned.source) {
+                (None, _) => missing.push(old_name.clone()),
+                (_, None) => unverified.push(old_name.clone()),
+                (current, planned) if current.as_ref() != planned.as_ref() => {
+                    changed.push(old_name.clone())
+                }
+                _ => {}
+            }
+            intents.push(RenameIntent::new(
+                old_name,
+                self.resolve(&planned.intent.new_name),
+            ));
+        }
+        if !missing.is_empty() {
+            return Err(MurenError::MissingSources(missing));
+        }
+        if !unverified.is_empty() {
+            return Err(MurenError::UnverifiedSources(unverified));
+        }
+        if !changed.is_empty() {
+            return Err(MurenError::SourcesChanged(changed));
+        }
+        Ok(intents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved_plan(dir: &Path) -> SavedPlan {
+        let intents = vec![RenameIntent::new(dir.join("a"), dir.join("b"))];
+        SavedPlan::new(intents).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "content").unwrap();
+        let plan = saved_plan(dir.path());
+        assert_eq!(plan.renames[0].source.as_ref().unwrap().size, 7);
+
+        let path = dir.path().join("plan.json");
+        fs::write(&path, serde_json::to_string(&plan).unwrap()).unwrap();
+        let loaded = SavedPlan::load(&path).unwrap();
+        assert_eq!(loaded.renames[0].source, plan.renames[0].source);
+        assert_eq!(loaded.renames[0].intent.new_name, dir.path().join("b"));
+
+        fs::write(&path, "not a plan").unwrap();
+        assert!(matches!(
+            SavedPlan::load(&path),
+            Err(MurenError::InvalidPlan(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_working_dir() {
+        use std::os::unix::ffi::OsStrExt;
+        let plan = SavedPlan {
+            working_dir: PathBuf::from(std::ffi::OsStr::from_bytes(b"/tmp/caf\xe9")),
+            renames: vec![],
+        };
+        let json = serde_json::to_string(&plan).unwrap();
+        let loaded: SavedPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.working_dir, plan.working_dir);
+    }
+
+    #[test]
+    fn test_apply_unchanged_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "content").unwrap();
+        let apply = Apply {
+            plan: saved_plan(dir.path()),
+        };
+        let intents = apply.suggest_renames(&[]).unwrap();
+        assert_eq!(intents[0].old_name, dir.path().join("a"));
+        assert_eq!(intents[0].new_name, dir.path().join("b"));
+    }
+
+    #[test]
+    fn test_apply_detects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), "content").unwrap();
+        let apply = Apply {
+            plan: saved_plan(dir.path()),
+        };
+
+        fs::write(dir.path().join("a"), "different content").unwrap();
+        assert!(matches!(
+            apply.suggest_renames(&[]),
+            Err(MurenError::SourcesChanged(paths)) if paths == [dir.path().join("a")]
+        ));
+
+        fs::remove_file(dir.path().join("a")).unwrap();
+        assert!(matches!(
+            apply.suggest_renames(&[]),
+            Err(MurenError::MissingSources(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = vec![RenameIntent::new(
+            dir.path().join("a"),
+            dir.path().join("b"),
+        )];
+        assert!(matches!(
+            SavedPlan::new(missing.clone()),
+            Err(MurenError::MissingSources(paths)) if paths == [dir.path().join("a")]
+        ));
+
+        // A plan written by hand, without the state of the file
+        let apply = Apply {
+            plan: SavedPlan {
+                working_dir: dir.path().to_path_buf(),
+                renames: vec![PlannedRename {
+                    intent: missing[0].clone(),
+                    source: None,
+                }],
+            },
+        };
+        assert!(matches!(
+            apply.suggest_renames(&[]),
+            Err(MurenError::MissingSources(_))
+        ));
+
+        // The file exists, but nothing tells whether it is the planned one
+        fs::write(dir.path().join("a"), "").unwrap();
+        assert!(matches!(
+            apply.suggest_renames(&[]),
+            Err(MurenError::UnverifiedSources(paths)) if paths == [dir.path().join("a")]
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::commands::{RenameCommand, RenameIntent};
+use crate::error::MurenError;
+
+/// The part of the path that a command renames.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Scope {
+    /// The file name, keeping the parent directory.
+    #[default]
+    Basename,
+    /// The file name without the extension.
+    Stem,
+    /// The extension only (files without one are left alone).
+    Extension,
+    /// The whole path as given.
+    Full,
+}
+
+impl Scope {
+    /// The part of the path to rename, `None` if the path does not have it.
+    pub fn part(&self, path: &Path) -> Option<PathBuf> {
+        match self {
+            Scope::Basename => path.file_name().map(PathBuf::from),
+            Scope::Stem => path.file_stem().map(PathBuf::from),
+            Scope::Extension => path.extension().map(PathBuf::from),
+            Scope::Full => Some(path.to_path_buf()),
+        }
+    }
+
+    /// The path with its part replaced.
+    pub fn replace_part(&self, path: &Path, new_part: &Path) -> PathBuf {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        match self {
+            Scope::Basename => parent.join(new_part),
+            Scope::Stem => {
+                let mut name = OsString::from(new_part);
+                if let Some(extension) = path.extension() {
+                    name.push(".");
+                    name.push(extension);
+                }
+                parent.join(name)
+            }
+            Scope::Extension => path.with_extension(new_part),
+            Scope::Full => new_part.to_path_buf(),
+        }
+    }
+}
+
+/// Apply a command to a part of the paths only.
+pub struct Scoped {
+    pub command: Box<dyn RenameCommand>,
+    pub scope: Scope,
+}
+
+impl RenameCommand for Scoped {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        Ok(self
+            .suggest_renames(&[old_name.to_path_buf()])?
+            .remove(0)
+            .new_name)
+    }
+
+    /// The parts are passed to the command at once (e.g. to be edited together).
+    fn suggest_renames(&self, files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
+        if self.scope == Scope::Full || self.command.needs_full_path() {
+            return self.command.suggest_renames(files);
+        }
+        let (indices, parts): (Vec<usize>, Vec<PathBuf>) = files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| self.scope.part(file).map(|part| (index, part)))
+            .unzip();
+
+        let mut new_names = files.to_vec();
+        for (index, intent) in indices
+            .into_iter()
+            .zip(self.command.suggest_renames(&parts)?)
+        {
+            new_names[index] = self.scope.replace_part(&files[index], &intent.new_name);
+        }
+        Ok(files
+            .iter()
+            .cloned()
+            .zip(new_names)
+            .map(|(old_name, new_name)| RenameIntent::new(old_name, new_name))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{ChangeCase, FixExtension, Normalize, Prefix};
+
+    fn new_names(command: Box<dyn RenameCommand>, scope: Scope, files: &[&str]) -> Vec<PathBuf> {
+        let files: Vec<PathBuf> = files.iter().map(PathBuf::from).collect();
+        Scoped { command, scope }
+            .suggest_renames(&files)
+            .unwrap()
+            .into_iter()
+            .map(|intent| intent.new_name)
+            .collect()
+    }
+
+    fn prefix() -> Box<dyn RenameCommand> {
+        Box::new(Prefix {
+            prefix: String::from("x_"),
+        })
+    }
+
+    #[test]
+    fn test_scopes() {
+        let files = ["photos/a.jpg", "b", ".gitignore"];
+        assert_eq!(
+            new_names(prefix(), Scope::Basename, &files),
+            ["photos/x_a.jpg", "x_b", "x_.gitignore"].map(PathBuf::from)
+        );
+        assert_eq!(
+            new_names(prefix(), Scope::Stem, &files),
+            ["photos/x_a.jpg", "x_b", "x_.gitignore"].map(PathBuf::from)
+        );
+        assert_eq!(
+            new_names(prefix(), Scope::Extension, &files),
+            ["photos/a.x_jpg", "b", ".gitignore"].map(PathBuf::from)
+        );
+        assert_eq!(
+            new_names(prefix(), Scope::Full, &files),
+            ["x_photos/a.jpg", "x_b", "x_.gitignore"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_parent_is_kept() {
+        assert_eq!(
+            new_names(
+                Box::new(Normalize { target: None }),
+                Scope::Basename,
+                &["Fotky léto/pláž.JPG"]
+            ),
+            [PathBuf::from("Fotky léto/plaz.JPG")]
+        );
+        assert_eq!(
+            new_names(
+                Box::new(ChangeCase { upper: false }),
+                Scope::Extension,
+                &["Photos/IMG.JPG"]
+            ),
+            [PathBuf::from("Photos/IMG.jpg")]
+        );
+    }
+
+    /// Uses the path as a suffix, to show what it got.
+    struct Echo;
+
+    impl RenameCommand for Echo {
+        fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+            Ok(PathBuf::from(format!(
+                "{0}-{0}",
+                old_name.to_string_lossy()
+            )))
+        }
+
+        fn needs_full_path(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_full_path_command() {
+        assert_eq!(
+            new_names(Box::new(Echo), Scope::Stem, &["dir/a"]),
+            [PathBuf::from("dir/a-dir/a")]
+        );
+        assert!(FixExtension { append: false }.needs_full_path());
+        assert!(crate::edit::Edit::from_env().needs_full_path());
+    }
+}
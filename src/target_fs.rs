@@ -0,0 +1,255 @@
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The filesystem that the renamed files should fit on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetFs {
+    /// Anything but `/` and NUL, up to 255 bytes (ext4, btrfs, ...).
+    #[default]
+    Posix,
+    /// NTFS as seen by Windows: no `<>:"/\|?*`, reserved device names or trailing dots and spaces.
+    Windows,
+    /// Long file names on FAT32, restricted like on Windows.
+    Fat32,
+    /// Like Windows, except that device names are allowed.
+    Exfat,
+    /// Level 2 without extensions: up to 31 of `A-Z`, `0-9` and `_`, with at most one dot.
+    Iso9660,
+    /// APFS and HFS+ as seen by the Finder: no `:`.
+    Macos,
+}
+
+/// Why a name does not fit on the target filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NameProblem {
+    Character(char),
+    NotUnicode,
+    /// A device name on Windows, like `CON` or `LPT1.txt`.
+    Reserved,
+    TrailingDotOrSpace,
+    TooLong,
+}
+
+impl Display for TargetFs {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            TargetFs::Posix => "posix",
+            TargetFs::Windows => "windows",
+            TargetFs::Fat32 => "fat32",
+            TargetFs::Exfat => "exfat",
+            TargetFs::Iso9660 => "iso9660",
+            TargetFs::Macos => "macos",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for NameProblem {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            NameProblem::Character(c) => write!(f, "contains {c:?}"),
+            NameProblem::NotUnicode => write!(f, "not valid Unicode"),
+            NameProblem::Reserved => write!(f, "reserved name"),
+            NameProblem::TrailingDotOrSpace => write!(f, "ends with a dot or space"),
+            NameProblem::TooLong => write!(f, "too long"),
+        }
+    }
+}
+
+const WINDOWS_CHARACTERS: &str = "<>:\"/\\|?*";
+const RESERVED_NAMES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+impl TargetFs {
+    /// The first problem of a single file name (not a path), if any.
+    pub fn check(&self, name: &OsStr) -> Option<NameProblem> {
+        let Some(text) = name.to_str() else {
+            return match self {
+                TargetFs::Posix => (name.len() > 255).then_some(NameProblem::TooLong),
+                _ => Some(NameProblem::NotUnicode),
+            };
+        };
+        if let Some(c) = text.chars().find(|&c| !self.allows(c)) {
+            return Some(NameProblem::Character(c));
+        }
+        if *self == TargetFs::Iso9660 && text.matches('.').count() > 1 {
+            return Some(NameProblem::Character('.'));
+        }
+        if self.is_windows_like() && text.ends_with(['.', ' ']) {
+            return Some(NameProblem::TrailingDotOrSpace);
+        }
+        if self.reserves_device_names() && is_device_name(text) {
+            return Some(NameProblem::Reserved);
+        }
+        (self.length(text) > self.max_length()).then_some(NameProblem::TooLong)
+    }
+
+    /// The first problem of the components of the path, if any.
+    pub fn check_path(&self, path: &Path) -> Option<NameProblem> {
+        path.components().find_map(|component| match component {
+            Component::Normal(name) => self.check(name),
+            _ => None,
+        })
+    }
+
+    /// Change the names in the path so that they fit, replacing the invalid characters by `_`.
+    pub fn fix_path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) if self.check(name).is_some() => {
+                    PathBuf::from(self.fix(name))
+                }
+                component => PathBuf::from(component.as_os_str()),
+            })
+            .collect()
+    }
+
+    fn fix(&self, name: &OsStr) -> String {
+        let mut text = String::new();
+        for chunk in name.as_encoded_bytes().utf8_chunks() {
+            text.push_str(chunk.valid());
+            text.extend(chunk.invalid().iter().map(|_| '_'));
+        }
+        if *self == TargetFs::Iso9660 {
+            text = text.to_uppercase();
+        }
+        let mut text: String = text
+            .chars()
+            .map(|c| match self.allows(c) {
+                true => c,
+                false => '_',
+            })
+            .collect();
+        if *self == TargetFs::Iso9660 {
+            if let Some(last_dot) = text.rfind('.') {
+                text = text[..last_dot].replace('.', "_") + &text[last_dot..];
+            }
+        }
+        if self.is_windows_like() {
+            text.truncate(text.trim_end_matches(['.', ' ']).len());
+        }
+        if text.is_empty() {
+            text.push('_');
+        }
+        if self.reserves_device_names() && is_device_name(&text) {
+            let stem_end = text.find('.').unwrap_or(text.len());
+            text.insert(stem_end, '_');
+        }
+        // Shorten the stem, keeping the extension
+        let extension = match text.rfind('.') {
+            Some(dot) if dot > 0 && self.length(&text[dot..]) < self.max_length() => {
+                text.split_off(dot)
+            }
+            _ => String::new(),
+        };
+        while self.length(&text) + self.length(&extension) > self.max_length() {
+            text.pop();
+        }
+        text + &extension
+    }
+
+    fn allows(&self, c: char) -> bool {
+        match self {
+            TargetFs::Posix => c != '\0',
+            TargetFs::Windows | TargetFs::Fat32 | TargetFs::Exfat => {
+                !c.is_ascii_control() && !WINDOWS_CHARACTERS.contains(c)
+            }
+            TargetFs::Iso9660 => c.is_ascii_uppercase() || c.is_ascii_digit() || "_.".contains(c),
+            TargetFs::Macos => c != '\0' && c != ':' && c != '/',
+        }
+    }
+
+    fn is_windows_like(&self) -> bool {
+        matches!(self, TargetFs::Windows | TargetFs::Fat32 | TargetFs::Exfat)
+    }
+
+    fn reserves_device_names(&self) -> bool {
+        matches!(self, TargetFs::Windows | TargetFs::Fat32)
+    }
+
+    fn max_length(&self) -> usize {
+        match self {
+            TargetFs::Iso9660 => 31,
+            _ => 255,
+        }
+    }
+
+    /// The length in the units of the filesystem's limit.
+    fn length(&self, text: &str) -> usize {
+        match self {
+            TargetFs::Posix | TargetFs::Macos => text.len(),
+            TargetFs::Windows | TargetFs::Fat32 | TargetFs::Exfat => text.encode_utf16().count(),
+            TargetFs::Iso9660 => text.chars().count(),
+        }
+    }
+}
+
+/// `CON`, `COM1` etc., also with an extension (`NUL.txt`).
+fn is_device_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    let upper = stem.to_ascii_uppercase();
+    RESERVED_NAMES.contains(&upper.as_str())
+        || (upper.len() == 4
+            && (upper.starts_with("COM") || upper.starts_with("LPT"))
+            && matches!(upper.as_bytes()[3], b'1'..=b'9'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(target: TargetFs, name: &str) -> Option<NameProblem> {
+        target.check(OsStr::new(name))
+    }
+
+    fn fix(target: TargetFs, name: &str) -> String {
+        target
+            .fix_path(Path::new(name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_check() {
+        use NameProblem::*;
+        assert_eq!(check(TargetFs::Posix, "a:b?.txt "), None);
+        assert_eq!(check(TargetFs::Windows, "a:b.txt"), Some(Character(':')));
+        assert_eq!(
+            check(TargetFs::Windows, "notes. "),
+            Some(TrailingDotOrSpace)
+        );
+        assert_eq!(check(TargetFs::Fat32, "con.txt"), Some(Reserved));
+        assert_eq!(check(TargetFs::Windows, "COM10"), None);
+        assert_eq!(check(TargetFs::Exfat, "CON"), None);
+        assert_eq!(check(TargetFs::Macos, "a:b"), Some(Character(':')));
+        assert_eq!(check(TargetFs::Iso9660, "README.TXT"), None);
+        assert_eq!(check(TargetFs::Iso9660, "readme.txt"), Some(Character('r')));
+        assert_eq!(check(TargetFs::Iso9660, "A.B.C"), Some(Character('.')));
+
+        let long = "é".repeat(200);
+        assert_eq!(check(TargetFs::Posix, &long), Some(TooLong));
+        assert_eq!(check(TargetFs::Windows, &long), None);
+        assert_eq!(
+            TargetFs::Windows.check_path(Path::new("ok/a|b/c")),
+            Some(Character('|'))
+        );
+    }
+
+    #[test]
+    fn test_fix() {
+        assert_eq!(fix(TargetFs::Windows, "dir/a:b?.txt. "), "dir/a_b_.txt");
+        assert_eq!(fix(TargetFs::Windows, "CON.tar.gz"), "CON_.tar.gz");
+        assert_eq!(fix(TargetFs::Windows, "..."), "_");
+        assert_eq!(fix(TargetFs::Windows, "good name.txt"), "good name.txt");
+        assert_eq!(fix(TargetFs::Iso9660, "my file.tar.gz"), "MY_FILE_TAR.GZ");
+
+        let long = format!("{}.jpeg", "x".repeat(300));
+        let fixed = fix(TargetFs::Windows, &long);
+        assert_eq!(fixed.len(), 255);
+        assert!(fixed.ends_with("x.jpeg"));
+        assert_eq!(check(TargetFs::Windows, &fixed), None);
+    }
+}
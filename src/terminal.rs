@@ -0,0 +1,363 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use colored::Colorize;
+
+use muren::commands::RenameIntent;
+use muren::diff::{Granularity, HighlightedIntent};
+use muren::error::MurenError;
+use muren::execute::Transfer;
+use muren::filter::{exclusion_summary, Exclusion};
+use muren::links::Relink;
+use muren::plan::{DirChange, RenameStep};
+use muren::report::{
+    status_before_execution, OutputFormat, RenameOutcome, RenameReport, RenameStatus, Reporter,
+    Review,
+};
+
+/// Colored, human-readable output.
+pub struct TerminalReporter {
+    pub show_unchanged: bool,
+    /// Highlight the changed parts of the names in these units.
+    pub granularity: Option<Granularity>,
+    /// Align the new names in a column.
+    pub columns: bool,
+    pub input: Input,
+}
+
+impl TerminalReporter {
+    /// Print all renames (and conflicts)
+    fn print_intents(&self, intents: &[RenameIntent], show_unchanged: bool) {
+        let shown: Vec<&RenameIntent> = intents
+            .iter()
+            .filter(|intent| intent.is_changed() || intent.conflict.is_some() || show_unchanged)
+            .collect();
+        let old_width = match self.columns {
+            true => shown
+                .iter()
+                .map(|intent| intent.old_name.to_string_lossy().chars().count())
+                .max()
+                .unwrap_or_default(),
+            false => 0,
+        };
+        for intent in shown {
+            println!("{}", self.highlighted(intent, old_width));
+        }
+    }
+
+    fn highlighted<'a>(&self, intent: &'a RenameIntent, old_width: usize) -> HighlightedIntent<'a> {
+        HighlightedIntent {
+            intent,
+            granularity: self.granularity,
+            old_width,
+        }
+    }
+}
+
+impl Reporter for TerminalReporter {
+    fn excluded(&self, files: &[(PathBuf, Exclusion)]) {
+        println!("{}", exclusion_summary(files));
+    }
+
+    fn preview(&self, intents: &[RenameIntent]) {
+        self.print_intents(intents, self.show_unchanged);
+    }
+
+    fn planned_dirs(&self, changes: &[DirChange]) {
+        changes
+            .iter()
+            .for_each(|change| println!("{}", dir_change(change)));
+    }
+
+    fn planned_relinks(&self, relinks: &[Relink]) {
+        relinks
+            .iter()
+            .for_each(|relink| println!("{}", relink_line(relink)));
+    }
+
+    fn confirm(&self, intents: &[RenameIntent]) -> Result<bool, MurenError> {
+        println!("The following files will be renamed:");
+        self.print_intents(intents, false);
+        println!("Do you want to continue? [y/N] ");
+        self.input.read_answer()
+    }
+
+    fn review(&self, intent: &RenameIntent) -> Result<Review, MurenError> {
+        ask_review(
+            intent,
+            &self.highlighted(intent, 0),
+            self.input,
+            &mut std::io::stdout(),
+        )
+    }
+
+    fn renamed(&self, step: &RenameStep, result: &Result<Transfer, String>) {
+        match result {
+            Ok(transfer) => println!(
+                "{0} {1} → {2}{3}",
+                "✓".green(),
+                step.from.to_string_lossy().red(),
+                step.to.to_string_lossy().green(),
+                match transfer {
+                    Transfer::Renamed => "",
+                    Transfer::Copied => " (copied across filesystems)",
+                    Transfer::CopiedKeepingSource(_) =>
+                        " (copied across filesystems, original kept)",
+                }
+            ),
+            Err(e) => eprintln!(
+                "{0} {1} → {2} ({3})",
+                "✗".red(),
+                step.from.to_string_lossy().red(),
+                step.to.to_string_lossy().green(),
+                e
+            ),
+        }
+    }
+
+    fn dir_changed(&self, change: &DirChange, result: &Result<(), String>) {
+        match result {
+            Ok(_) => println!("{0} {1}", "✓".green(), dir_change(change)),
+            Err(e) => eprintln!("{0} {1} ({2})", "✗".red(), dir_change(change), e),
+        }
+    }
+
+    fn relinked(&self, relink: &Relink, result: &Result<(), String>) {
+        match result {
+            Ok(_) => println!("{0} {1}", "✓".green(), relink_line(relink)),
+            Err(e) => eprintln!("{0} {1} ({2})", "✗".red(), relink_line(relink), e),
+        }
+    }
+
+    fn rolling_back(&self, count: usize) {
+        if count == 0 {
+            println!("Nothing to roll back.");
+        } else {
+            println!("Rolling back {count} renames:");
+        }
+    }
+
+    fn rolled_back(&self, step: &RenameStep, result: &Result<Transfer, String>) {
+        match result {
+            Ok(_) => println!(
+                "{0} {1} → {2}",
+                "↺".yellow(),
+                step.to.to_string_lossy().green(),
+                step.from.to_string_lossy().red()
+            ),
+            Err(e) => eprintln!(
+                "{0} {1} → {2} ({3})",
+                "✗".red(),
+                step.to.to_string_lossy().green(),
+                step.from.to_string_lossy().red(),
+                e
+            ),
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        eprintln!("{}", message.yellow());
+    }
+
+    fn finished(&self, report: &RenameReport) {
+        if report.dry {
+            return;
+        }
+        if self.show_unchanged {
+            report
+                .outcomes
+                .iter()
+                .filter(|outcome| outcome.status == RenameStatus::Unchanged)
+                .for_each(|outcome| println!("{}", self.highlighted(&outcome.intent, 0)));
+        }
+
+        let totals = report.totals();
+        if totals.not_restored > 0 {
+            eprintln!("{} files could not be restored:", totals.not_restored);
+            for outcome in &report.outcomes {
+                if let RenameStatus::NotRestored { left_at, .. } = &outcome.status {
+                    eprintln!(
+                        "  {} is left as {}",
+                        outcome.intent.old_name.to_string_lossy(),
+                        left_at.to_string_lossy()
+                    );
+                }
+            }
+        } else if totals.rolled_back > 0 {
+            println!("All renames rolled back.");
+        }
+        println!("{} files renamed.", totals.renamed);
+    }
+}
+
+/// Machine-readable output on stdout, everything meant for humans goes to stderr.
+pub struct FormattedReporter {
+    pub format: OutputFormat,
+    pub input: Input,
+}
+
+impl FormattedReporter {
+    fn write(&self, outcomes: &[RenameOutcome]) {
+        if let Err(e) = self.format.write(outcomes, &mut std::io::stdout().lock()) {
+            eprintln!("Could not write the output: {e}");
+        }
+    }
+}
+
+impl Reporter for FormattedReporter {
+    fn excluded(&self, files: &[(PathBuf, Exclusion)]) {
+        eprintln!("{}", exclusion_summary(files));
+    }
+
+    fn preview(&self, intents: &[RenameIntent]) {
+        // Dry runs are written in `finished`, only the conflicts stopping the batch are written here.
+        if intents.iter().any(|intent| intent.is_blocked()) {
+            let outcomes: Vec<RenameOutcome> = intents
+                .iter()
+                .map(|intent| RenameOutcome {
+                    intent: intent.clone(),
+                    status: status_before_execution(intent),
+                })
+                .collect();
+            self.write(&outcomes);
+        }
+    }
+
+    fn planned_dirs(&self, changes: &[DirChange]) {
+        changes
+            .iter()
+            .for_each(|change| eprintln!("{}", dir_change(change)));
+    }
+
+    fn planned_relinks(&self, relinks: &[Relink]) {
+        relinks
+            .iter()
+            .for_each(|relink| eprintln!("{}", relink_line(relink)));
+    }
+
+    fn confirm(&self, intents: &[RenameIntent]) -> Result<bool, MurenError> {
+        eprintln!("The following files will be renamed:");
+        intents
+            .iter()
+            .filter(|intent| intent.is_changed())
+            .for_each(|intent| eprintln!("{}", intent));
+        eprintln!("Do you want to continue? [y/N] ");
+        self.input.read_answer()
+    }
+
+    fn review(&self, intent: &RenameIntent) -> Result<Review, MurenError> {
+        ask_review(intent, intent, self.input, &mut std::io::stderr())
+    }
+
+    fn dir_changed(&self, change: &DirChange, result: &Result<(), String>) {
+        if let Err(e) = result {
+            eprintln!("{0} {1} ({2})", "✗".red(), dir_change(change), e);
+        }
+    }
+
+    fn relinked(&self, relink: &Relink, result: &Result<(), String>) {
+        if let Err(e) = result {
+            eprintln!("{0} {1} ({2})", "✗".red(), relink_line(relink), e);
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        eprintln!("{}", message.yellow());
+    }
+
+    fn finished(&self, report: &RenameReport) {
+        self.write(&report.outcomes);
+    }
+}
+
+/// A created (`+ dir/`) or removed (`- dir/`) directory.
+fn dir_change(change: &DirChange) -> String {
+    match change {
+        DirChange::Created(dir) => format!("{0} {1}/", "+".green(), dir.to_string_lossy().green()),
+        DirChange::Removed(dir) => format!("{0} {1}/", "-".red(), dir.to_string_lossy().red()),
+    }
+}
+
+/// A link with its old and new target (`link -> old ⇒ new`).
+fn relink_line(relink: &Relink) -> String {
+    format!(
+        "{0} -> {1} ⇒ {2}",
+        relink.link.to_string_lossy(),
+        relink.old_target.to_string_lossy().red(),
+        relink.new_target.to_string_lossy().green()
+    )
+}
+
+/// Where the answers to the questions are read from.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Input {
+    #[default]
+    Stdin,
+    /// The terminal, when stdin is used for the paths.
+    Tty,
+}
+
+#[cfg(windows)]
+const TTY: &str = "CONIN$";
+#[cfg(not(windows))]
+const TTY: &str = "/dev/tty";
+
+impl Input {
+    /// The end of the input means no.
+    fn read_answer(&self) -> Result<bool, MurenError> {
+        Ok(self
+            .read_line()?
+            .is_some_and(|answer| answer.to_lowercase() == "y"))
+    }
+
+    /// The trimmed line, `None` at the end of the input.
+    fn read_line(&self) -> Result<Option<String>, MurenError> {
+        let mut input = String::new();
+        let read = match self {
+            Input::Stdin => io::stdin().read_line(&mut input)?,
+            Input::Tty => BufReader::new(File::open(TTY)?).read_line(&mut input)?,
+        };
+        Ok((read > 0).then(|| input.trim().to_string()))
+    }
+}
+
+/// Ask about a single rename until a valid answer is given.
+fn ask_review(
+    intent: &RenameIntent,
+    shown: &dyn Display,
+    input: Input,
+    out: &mut dyn Write,
+) -> Result<Review, MurenError> {
+    loop {
+        write!(out, "{shown} Rename? [y,n,e,a,q,?] ")?;
+        out.flush()?;
+        // Nobody is left to answer at the end of the input
+        let Some(answer) = input.read_line()? else {
+            return Ok(Review::Quit);
+        };
+        match answer.to_lowercase().as_str() {
+            "y" => return Ok(Review::Accept),
+            "n" => return Ok(Review::Decline),
+            "a" => return Ok(Review::AcceptAll),
+            "q" => return Ok(Review::Quit),
+            "e" => {
+                write!(out, "New name [{}]: ", intent.new_name.to_string_lossy())?;
+                out.flush()?;
+                let Some(new_name) = input.read_line()? else {
+                    return Ok(Review::Quit);
+                };
+                if new_name.is_empty() {
+                    return Ok(Review::Accept);
+                }
+                return Ok(Review::Rename(PathBuf::from(new_name)));
+            }
+            _ => writeln!(
+                out,
+                "y - rename\nn - keep the name\ne - edit the new name\n\
+                 a - rename this and all remaining files\nq - keep the names of this and all remaining files"
+            )?,
+        }
+    }
+}
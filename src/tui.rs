@@ -0,0 +1,569 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use muren::commands::{
+    ChangeCase, FixExtension, Normalize, Prefix, Remove, RenameCommand, RenameIntent, Replace,
+    SetExtension,
+};
+use muren::comparison::NameComparison;
+use muren::error::MurenError;
+use muren::plan::{
+    check_names, normalize_new_names, resolve_conflicts, skip_lossy_names, ConflictPolicy,
+};
+use muren::scope::{Scope, Scoped};
+use muren::target_fs::TargetFs;
+
+/// The renames chosen in the TUI.
+pub struct Chosen {
+    pub intents: Vec<RenameIntent>,
+}
+
+impl RenameCommand for Chosen {
+    fn suggest_new_name(&self, old_name: &Path) -> Result<PathBuf, MurenError> {
+        Ok(self
+            .intents
+            .iter()
+            .find(|intent| intent.old_name == old_name)
+            .map(|intent| intent.new_name.clone())
+            .unwrap_or_else(|| old_name.to_path_buf()))
+    }
+
+    /// The renames chosen in the TUI, for those of the files it was given.
+    fn suggest_renames(&self, files: &[PathBuf]) -> Result<Vec<RenameIntent>, MurenError> {
+        let files: HashSet<&PathBuf> = files.iter().collect();
+        Ok(self
+            .intents
+            .iter()
+            .filter(|intent| files.contains(&intent.old_name))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Let the user build the renames interactively, returning nothing if they quit.
+pub fn choose_renames(
+    files: &[PathBuf],
+    scope: Scope,
+    policy: &ConflictPolicy,
+    comparison: Option<NameComparison>,
+    target_fs: TargetFs,
+) -> Result<Vec<RenameIntent>, MurenError> {
+    let mut app = App::new(
+        files.to_vec(),
+        scope,
+        policy.clone(),
+        comparison.unwrap_or_else(|| NameComparison::detect(files)),
+        target_fs,
+    );
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    Ok(match result? {
+        Exit::Commit => app.chosen(),
+        Exit::Quit => vec![],
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Replace,
+    Remove,
+    Prefix,
+    SetExtension,
+    ChangeCase,
+    Normalize,
+    FixExtension,
+}
+
+const KINDS: [Kind; 7] = [
+    Kind::Replace,
+    Kind::Remove,
+    Kind::Prefix,
+    Kind::SetExtension,
+    Kind::ChangeCase,
+    Kind::Normalize,
+    Kind::FixExtension,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Text(&'static str),
+    Flag(&'static str),
+}
+
+impl Kind {
+    /// The same name as the subcommand.
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Replace => "replace",
+            Kind::Remove => "remove",
+            Kind::Prefix => "prefix",
+            Kind::SetExtension => "set-ext",
+            Kind::ChangeCase => "change-case",
+            Kind::Normalize => "normalize",
+            Kind::FixExtension => "fix-ext",
+        }
+    }
+
+    fn fields(&self) -> &'static [Field] {
+        match self {
+            Kind::Replace => &[
+                Field::Text("pattern"),
+                Field::Text("replacement"),
+                Field::Flag("regex"),
+            ],
+            Kind::Remove => &[Field::Text("pattern")],
+            Kind::Prefix => &[Field::Text("prefix")],
+            Kind::SetExtension => &[Field::Text("extension")],
+            Kind::ChangeCase => &[Field::Flag("upper")],
+            Kind::Normalize => &[],
+            Kind::FixExtension => &[Field::Flag("append")],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Focus {
+    Command,
+    Field(usize),
+    Files,
+}
+
+#[derive(Debug, PartialEq)]
+enum Exit {
+    Commit,
+    Quit,
+}
+
+struct App {
+    files: Vec<PathBuf>,
+    scope: Scope,
+    policy: ConflictPolicy,
+    comparison: NameComparison,
+    target_fs: TargetFs,
+    kind: usize,
+    /// Values of the fields, shared by the commands (e.g. the pattern of replace and remove).
+    texts: HashMap<&'static str, String>,
+    flags: HashSet<&'static str>,
+    /// Indices of the files that keep their names.
+    disabled: HashSet<usize>,
+    focus: Focus,
+    selected: ListState,
+    preview: Result<Vec<RenameIntent>, String>,
+    message: Option<String>,
+}
+
+impl App {
+    fn new(
+        files: Vec<PathBuf>,
+        scope: Scope,
+        policy: ConflictPolicy,
+        comparison: NameComparison,
+        target_fs: TargetFs,
+    ) -> Self {
+        let mut app = App {
+            files,
+            scope,
+            policy,
+            comparison,
+            target_fs,
+            kind: 0,
+            texts: HashMap::new(),
+            flags: HashSet::new(),
+            disabled: HashSet::new(),
+            focus: Focus::Field(0),
+            selected: ListState::default().with_selected(Some(0)),
+            preview: Ok(vec![]),
+            message: None,
+        };
+        app.update();
+        app
+    }
+
+    fn kind(&self) -> Kind {
+        KINDS[self.kind]
+    }
+
+    fn text(&self, name: &str) -> String {
+        self.texts.get(name).cloned().unwrap_or_default()
+    }
+
+    /// The command for the current fields, `None` if it would not change anything yet.
+    fn command(&self) -> Option<Box<dyn RenameCommand>> {
+        Some(match self.kind() {
+            Kind::Replace if self.text("pattern").is_empty() => return None,
+            Kind::Replace => Box::new(Replace {
+                pattern: self.text("pattern"),
+                replacement: self.text("replacement"),
+                is_regex: self.flags.contains("regex"),
+            }),
+            Kind::Remove if self.text("pattern").is_empty() => return None,
+            Kind::Remove => Box::new(Remove {
+                pattern: self.text("pattern"),
+            }),
+            Kind::Prefix => Box::new(Prefix {
+                prefix: self.text("prefix"),
+            }),
+            Kind::SetExtension => Box::new(SetExtension {
+                extension: self.text("extension"),
+            }),
+            Kind::ChangeCase => Box::new(ChangeCase {
+                upper: self.flags.contains("upper"),
+            }),
+            Kind::Normalize => Box::new(Normalize { target: None }),
+            Kind::FixExtension => Box::new(FixExtension {
+                append: self.flags.contains("append"),
+            }),
+        })
+    }
+
+    /// Suggest the renames again after any change.
+    fn update(&mut self) {
+        let suggested = match self.command() {
+            Some(command) => Scoped {
+                command,
+                scope: self.scope,
+            }
+            .suggest_renames(&self.files),
+            None => Ok(self
+                .files
+                .iter()
+                .map(|file| RenameIntent::new(file.clone(), file.clone()))
+                .collect()),
+        };
+        self.preview = suggested
+            .map(|mut intents| {
+                for (index, intent) in intents.iter_mut().enumerate() {
+                    if self.disabled.contains(&index) {
+                        intent.new_name = intent.old_name.clone();
+                    }
+                }
+                // The same checks as before the renames are performed
+                normalize_new_names(&mut intents);
+                skip_lossy_names(&mut intents);
+                check_names(&mut intents, self.target_fs, &self.policy);
+                resolve_conflicts(&mut intents, &self.policy, self.comparison);
+                intents
+            })
+            .map_err(|e| e.to_string());
+    }
+
+    fn chosen(&self) -> Vec<RenameIntent> {
+        match &self.preview {
+            Ok(intents) => intents
+                .iter()
+                .map(|intent| RenameIntent::new(intent.old_name.clone(), intent.new_name.clone()))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Exit, MurenError> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(exit) = self.handle_key(key) {
+                    return Ok(exit);
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Exit> {
+        self.message = None;
+        let field_count = self.kind().fields().len();
+        match (key.code, self.focus) {
+            (KeyCode::Esc, _) => return Some(Exit::Quit),
+            (KeyCode::Char('c'), _) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Exit::Quit)
+            }
+            (KeyCode::Enter, _) => match &self.preview {
+                Err(e) => self.message = Some(e.clone()),
+                Ok(intents) if intents.iter().any(|intent| intent.is_blocked()) => {
+                    self.message = Some(String::from(
+                        "Resolve the conflicts first (e.g. by toggling the files off).",
+                    ))
+                }
+                Ok(_) => return Some(Exit::Commit),
+            },
+            (KeyCode::Tab, focus) => {
+                self.focus = match focus {
+                    Focus::Command if field_count > 0 => Focus::Field(0),
+                    Focus::Field(index) if index + 1 < field_count => Focus::Field(index + 1),
+                    Focus::Files => Focus::Command,
+                    _ => Focus::Files,
+                }
+            }
+            (KeyCode::BackTab, focus) => {
+                self.focus = match focus {
+                    Focus::Files if field_count > 0 => Focus::Field(field_count - 1),
+                    Focus::Field(index) if index > 0 => Focus::Field(index - 1),
+                    Focus::Command => Focus::Files,
+                    _ => Focus::Command,
+                }
+            }
+            (KeyCode::Left | KeyCode::Right, Focus::Command) => {
+                self.kind = if key.code == KeyCode::Left {
+                    (self.kind + KINDS.len() - 1) % KINDS.len()
+                } else {
+                    (self.kind + 1) % KINDS.len()
+                };
+                self.update();
+            }
+            (KeyCode::Up, Focus::Files) => self.selected.select_previous(),
+            (KeyCode::Down, Focus::Files) => self.selected.select_next(),
+            (KeyCode::Char(' '), Focus::Files) => {
+                if let Some(index) = self.selected.selected() {
+                    if !self.disabled.remove(&index) {
+                        self.disabled.insert(index);
+                    }
+                    self.update();
+                }
+            }
+            (code, Focus::Field(index)) => match (self.kind().fields()[index], code) {
+                (Field::Text(name), KeyCode::Char(c)) => {
+                    self.texts.entry(name).or_default().push(c);
+                    self.update();
+                }
+                (Field::Text(name), KeyCode::Backspace) => {
+                    self.texts.entry(name).or_default().pop();
+                    self.update();
+                }
+                (Field::Flag(name), KeyCode::Char(' ')) => {
+                    if !self.flags.remove(name) {
+                        self.flags.insert(name);
+                    }
+                    self.update();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let fields = self.kind().fields();
+        let [header, list, footer] = Layout::vertical([
+            Constraint::Length(fields.len() as u16 + 3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let focused = |focus| {
+            if self.focus == focus {
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::new()
+            }
+        };
+        let mut lines = vec![Line::from(vec![
+            Span::raw("command: "),
+            Span::styled(
+                format!("◀ {} ▶", self.kind().name()),
+                focused(Focus::Command),
+            ),
+        ])];
+        for (index, field) in fields.iter().enumerate() {
+            lines.push(match field {
+                Field::Text(name) => Line::from(vec![
+                    Span::raw(format!("{name}: ")),
+                    Span::styled(
+                        format!("{}▏", self.text(name)),
+                        focused(Focus::Field(index)),
+                    ),
+                ]),
+                Field::Flag(name) => Line::from(Span::styled(
+                    format!(
+                        "[{}] {name}",
+                        if self.flags.contains(name) { "x" } else { " " }
+                    ),
+                    focused(Focus::Field(index)),
+                )),
+            });
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" muren ")),
+            header,
+        );
+
+        let (items, title) = match &self.preview {
+            Ok(intents) => {
+                let changed = intents.iter().filter(|i| i.is_changed()).count();
+                let blocked = intents.iter().filter(|i| i.is_blocked()).count();
+                let items: Vec<ListItem> = intents
+                    .iter()
+                    .enumerate()
+                    .map(|(index, intent)| self.preview_line(index, intent))
+                    .collect();
+                (items, format!(" {changed} to rename, {blocked} conflicts "))
+            }
+            Err(e) => (vec![], format!(" {e} ")),
+        };
+        let list_block = Block::bordered()
+            .title(title)
+            .border_style(match self.focus {
+                Focus::Files => Style::new().bold(),
+                _ => Style::new(),
+            });
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(list_block)
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list,
+            &mut self.selected,
+        );
+
+        let help = match &self.message {
+            Some(message) => Line::from(message.as_str().red()),
+            None => Line::from(
+                "Tab: next field  ←/→: command  Space: toggle  Enter: rename  Esc: quit"
+                    .dark_gray(),
+            ),
+        };
+        frame.render_widget(Paragraph::new(help), footer);
+    }
+
+    fn preview_line(&self, index: usize, intent: &RenameIntent) -> ListItem<'static> {
+        let enabled = !self.disabled.contains(&index);
+        let mut spans = vec![Span::raw(if enabled { "[x] " } else { "[ ] " })];
+        let old_name = intent.old_name.to_string_lossy().to_string();
+        if intent.is_changed() {
+            spans.push(old_name.red());
+            spans.push(Span::raw(" → "));
+            spans.push(intent.new_name.to_string_lossy().to_string().green());
+        } else {
+            spans.push(Span::raw(old_name));
+            spans.push(Span::raw(" ="));
+        }
+        match (&intent.conflict, &intent.resolution) {
+            (Some(conflict), Some(resolution)) => {
+                spans.push(format!(" ({conflict}, {resolution})").yellow())
+            }
+            (Some(conflict), None) => spans.push(format!(" ({conflict})").red().bold()),
+            _ => {}
+        }
+        let line = Line::from(spans);
+        ListItem::new(if enabled {
+            line
+        } else {
+            line.style(Style::new().fg(Color::DarkGray))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> App {
+        let files = ["a.txt", "b.txt"].iter().map(PathBuf::from).collect();
+        App::new(
+            files,
+            Scope::Basename,
+            ConflictPolicy::Fail,
+            NameComparison::Exact,
+            TargetFs::Windows,
+        )
+    }
+
+    fn press(app: &mut App, codes: &[KeyCode]) -> Option<Exit> {
+        codes
+            .iter()
+            .map(|code| app.handle_key(KeyEvent::from(*code)))
+            .last()
+            .flatten()
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        let codes: Vec<KeyCode> = text.chars().map(KeyCode::Char).collect();
+        press(app, &codes);
+    }
+
+    fn new_names(app: &App) -> Vec<String> {
+        app.chosen()
+            .iter()
+            .map(|intent| intent.new_name.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_live_preview() {
+        let mut app = app();
+        assert_eq!(new_names(&app), ["a.txt", "b.txt"]);
+
+        type_text(&mut app, "txt");
+        press(&mut app, &[KeyCode::Tab]);
+        type_text(&mut app, "md");
+        assert_eq!(new_names(&app), ["a.md", "b.md"]);
+
+        press(&mut app, &[KeyCode::Backspace]);
+        assert_eq!(new_names(&app), ["a.m", "b.m"]);
+    }
+
+    #[test]
+    fn test_switch_command() {
+        let mut app = app();
+        press(
+            &mut app,
+            &[KeyCode::BackTab, KeyCode::Right, KeyCode::Right],
+        );
+        assert_eq!(app.kind(), Kind::Prefix);
+        press(&mut app, &[KeyCode::Tab]);
+        type_text(&mut app, "x_");
+        assert_eq!(new_names(&app), ["x_a.txt", "x_b.txt"]);
+    }
+
+    #[test]
+    fn test_toggle_files_and_conflicts() {
+        let mut app = app();
+        type_text(&mut app, "[ab]");
+        press(&mut app, &[KeyCode::Tab]);
+        type_text(&mut app, "c");
+        press(&mut app, &[KeyCode::Tab, KeyCode::Char(' ')]);
+        assert_eq!(new_names(&app), ["c.txt", "c.txt"]);
+
+        // Conflicting renames cannot be committed
+        assert_eq!(press(&mut app, &[KeyCode::Enter]), None);
+        assert!(app.message.is_some());
+
+        press(&mut app, &[KeyCode::Tab, KeyCode::Down, KeyCode::Char(' ')]);
+        assert_eq!(new_names(&app), ["c.txt", "b.txt"]);
+        assert_eq!(press(&mut app, &[KeyCode::Enter]), Some(Exit::Commit));
+        assert_eq!(press(&mut app, &[KeyCode::Esc]), Some(Exit::Quit));
+    }
+
+    #[test]
+    fn test_invalid_names_block_commit() {
+        let mut app = app();
+        type_text(&mut app, "a");
+        press(&mut app, &[KeyCode::Tab]);
+        type_text(&mut app, "a:");
+        assert!(app.preview.as_ref().unwrap()[0].is_blocked());
+        assert_eq!(press(&mut app, &[KeyCode::Enter]), None);
+    }
+
+    #[test]
+    fn test_chosen_keeps_given_files() {
+        let chosen = Chosen {
+            intents: vec![
+                RenameIntent::new(PathBuf::from("a"), PathBuf::from("A")),
+                RenameIntent::new(PathBuf::from("b"), PathBuf::from("B")),
+            ],
+        };
+        let intents = chosen.suggest_renames(&[PathBuf::from("b")]).unwrap();
+        assert_eq!(intents.len(), 1);
+        assert_eq!(intents[0].new_name, PathBuf::from("B"));
+    }
+}
@@ -0,0 +1,319 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use glob::{glob_with, MatchOptions};
+use ignore::WalkBuilder;
+
+use crate::error::MurenError;
+
+/// Kinds of filesystem entries to rename.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl FileType {
+    fn of(file_type: fs::FileType) -> Option<FileType> {
+        if file_type.is_symlink() {
+            Some(FileType::Symlink)
+        } else if file_type.is_dir() {
+            Some(FileType::Dir)
+        } else if file_type.is_file() {
+            Some(FileType::File)
+        } else {
+            None
+        }
+    }
+}
+
+/// Which files to find in the given paths.
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// Descend into the given directories (instead of renaming them).
+    pub recursive: bool,
+    /// How deep to descend, 1 being the contents of the given directories.
+    pub max_depth: Option<usize>,
+    /// Include hidden files (dotfiles) when descending.
+    pub hidden: bool,
+    /// Only these types of entries (all if empty).
+    pub file_types: Vec<FileType>,
+    /// Skip files ignored by `.gitignore` and `.ignore` files.
+    pub respect_ignore: bool,
+    /// Match glob patterns regardless of case.
+    pub glob_case_insensitive: bool,
+    /// Take the paths as they are, without expanding glob patterns.
+    pub literal: bool,
+}
+
+impl WalkOptions {
+    fn accepts(&self, path: &Path) -> bool {
+        if self.file_types.is_empty() {
+            return true;
+        }
+        match path.symlink_metadata() {
+            Ok(metadata) => FileType::of(metadata.file_type())
+                .is_some_and(|file_type| self.file_types.contains(&file_type)),
+            // Let the missing files fail later, with the other errors
+            Err(_) => true,
+        }
+    }
+}
+
+/// The files to rename: the given paths, or their contents when recursive.
+///
+/// The contents of a directory are listed before the directory itself,
+/// so that they are renamed while their path is still valid.
+pub fn collect_files(paths: &[PathBuf], options: &WalkOptions) -> Result<Vec<PathBuf>, MurenError> {
+    let paths = match options.literal {
+        true => paths.to_vec(),
+        false => expand_globs(paths, options.glob_case_insensitive)?,
+    };
+    if !options.recursive {
+        return Ok(paths
+            .iter()
+            .filter(|path| options.accepts(path))
+            .cloned()
+            .collect());
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        let walker = WalkBuilder::new(path)
+            .max_depth(options.max_depth)
+            .hidden(!options.hidden)
+            .ignore(options.respect_ignore)
+            .git_ignore(options.respect_ignore)
+            .git_global(options.respect_ignore)
+            .git_exclude(options.respect_ignore)
+            .parents(options.respect_ignore)
+            .require_git(false)
+            .build();
+        for entry in walker {
+            let entry = entry.map_err(|e| MurenError::Io(io::Error::other(e)))?;
+            // The given directories are only searched
+            if entry.depth() == 0 && entry.file_type().is_some_and(|t| t.is_dir()) {
+                continue;
+            }
+            if options.accepts(entry.path()) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    contents_first(&mut files);
+    files.dedup();
+    Ok(files)
+}
+
+/// Replace the glob patterns with the matching paths.
+///
+/// Arguments that exist as they are (e.g. `photo [1].jpg`) are not treated as patterns.
+pub fn expand_globs(paths: &[PathBuf], case_insensitive: bool) -> Result<Vec<PathBuf>, MurenError> {
+    let options = MatchOptions {
+        case_sensitive: !case_insensitive,
+        require_literal_separator: true,
+        require_literal_leading_dot: true,
+    };
+    let mut expanded = Vec::new();
+    for path in paths {
+        let pattern = match path.to_str() {
+            Some(pattern)
+                if pattern.contains(['*', '?', '[']) && path.symlink_metadata().is_err() =>
+            {
+                pattern
+            }
+            _ => {
+                expanded.push(path.clone());
+                continue;
+            }
+        };
+        let mut matches = glob_with(pattern, options)
+            .map_err(MurenError::InvalidGlob)?
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(|e| MurenError::Io(e.into()))?;
+        if matches.is_empty() {
+            return Err(MurenError::NoMatches(pattern.to_string()));
+        }
+        contents_first(&mut matches);
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/// Read a list of paths, one per line or separated by NUL characters (as `find -print0`).
+///
+/// Empty entries are skipped. On unix, the names are kept byte for byte.
+pub fn read_paths(mut reader: impl BufRead, null_separated: bool) -> io::Result<Vec<PathBuf>> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+    let mut paths = Vec::new();
+    let mut entry = Vec::new();
+    while reader.read_until(separator, &mut entry)? > 0 {
+        if entry.last() == Some(&separator) {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            paths.push(PathBuf::from(os_string(std::mem::take(&mut entry))));
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Sort the paths so that the contents of directories come before the directories.
+fn contents_first(paths: &mut [PathBuf]) {
+    paths.sort_by(|a, b| {
+        b.components()
+            .count()
+            .cmp(&a.components().count())
+            .then_with(|| a.cmp(b))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// dir/{a, .hidden, ignored.log, sub/{b, sub2/c}} with `*.log` in .gitignore
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sub/sub2")).unwrap();
+        for file in ["a", ".hidden", "ignored.log", "sub/b", "sub/sub2/c"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        dir
+    }
+
+    fn names(dir: &Path, options: &WalkOptions) -> Vec<String> {
+        collect_files(&[dir.to_path_buf()], options)
+            .unwrap()
+            .iter()
+            .map(|path| {
+                path.strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recursive() {
+        let dir = tree();
+        let options = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(dir.path(), &options),
+            ["sub/sub2/c", "sub/b", "sub/sub2", "a", "ignored.log", "sub"]
+        );
+
+        let options = WalkOptions {
+            recursive: true,
+            max_depth: Some(1),
+            hidden: true,
+            respect_ignore: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            names(dir.path(), &options),
+            [".gitignore", ".hidden", "a", "sub"]
+        );
+    }
+
+    #[test]
+    fn test_file_types() {
+        let dir = tree();
+        let options = WalkOptions {
+            recursive: true,
+            file_types: vec![FileType::Dir],
+            ..Default::default()
+        };
+        assert_eq!(names(dir.path(), &options), ["sub/sub2", "sub"]);
+
+        // Without recursion, only the given paths are filtered
+        let options = WalkOptions {
+            file_types: vec![FileType::File],
+            ..Default::default()
+        };
+        let paths = [dir.path().join("a"), dir.path().join("sub")];
+        assert_eq!(
+            collect_files(&paths, &options).unwrap(),
+            [dir.path().join("a")]
+        );
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let dir = tree();
+        fs::write(dir.path().join("B.LOG"), "").unwrap();
+        fs::write(dir.path().join("[x].log"), "").unwrap();
+        let pattern = |p: &str| dir.path().join(p);
+
+        assert_eq!(
+            expand_globs(&[pattern("**/*.log")], false).unwrap(),
+            [pattern("[x].log"), pattern("ignored.log")]
+        );
+        assert_eq!(
+            expand_globs(&[pattern("*.log")], true).unwrap(),
+            [pattern("B.LOG"), pattern("[x].log"), pattern("ignored.log")]
+        );
+        assert_eq!(
+            expand_globs(&[pattern("**/?")], false).unwrap(),
+            [pattern("sub/sub2/c"), pattern("sub/b"), pattern("a")]
+        );
+        // Existing files are taken literally
+        assert_eq!(
+            expand_globs(&[pattern("[x].log")], false).unwrap(),
+            [pattern("[x].log")]
+        );
+        assert!(matches!(
+            expand_globs(&[pattern("*.jpg")], false),
+            Err(MurenError::NoMatches(_))
+        ));
+        assert!(matches!(
+            expand_globs(&[pattern("[*.jpg")], false),
+            Err(MurenError::InvalidGlob(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_paths() {
+        let input = "a b.txt\nline\nbreak\n\nc\n";
+        assert_eq!(
+            read_paths(input.as_bytes(), false).unwrap(),
+            ["a b.txt", "line", "break", "c"].map(PathBuf::from)
+        );
+        let input = "a b.txt\0line\nbreak\0c";
+        assert_eq!(
+            read_paths(input.as_bytes(), true).unwrap(),
+            ["a b.txt", "line\nbreak", "c"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_literal_paths() {
+        let dir = tree();
+        let options = WalkOptions {
+            literal: true,
+            ..Default::default()
+        };
+        let paths = [dir.path().join("*")];
+        assert_eq!(collect_files(&paths, &options).unwrap(), paths);
+    }
+}